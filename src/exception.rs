@@ -6,7 +6,7 @@ use std::{
 
 pub type Result<T> = core::result::Result<T, Box<dyn Exception>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Position {
     pub line: usize,
     pub column: (usize, usize),
@@ -30,6 +30,9 @@ impl Display for Position {
 }
 
 pub trait Exception {
+    fn level(&self) -> &'static str {
+        "Compiler Exception"
+    }
     fn error(&self) -> &'static str;
     fn details(&self) -> &str;
     fn file(&self) -> Option<&str>;
@@ -38,7 +41,7 @@ pub trait Exception {
 
 impl Display for dyn Exception {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Compiler Exception: {} :: ", self.error())?;
+        write!(f, "{}: {} :: ", self.level(), self.error())?;
         if let Some(file) = self.file() {
             write!(f, "file: {} :: ", file)?;
         }
@@ -175,18 +178,18 @@ impl Exception for MprocessorException {
     }
 }
 
-pub struct NoMain(pub Rc<String>);
+pub struct NoMain(pub String, pub Rc<String>);
 impl Exception for NoMain {
     fn error(&self) -> &'static str {
         "No Main"
     }
 
     fn details(&self) -> &str {
-        "No Main function was found"
+        &self.0
     }
 
     fn file(&self) -> Option<&str> {
-        Some(self.0.as_str())
+        Some(self.1.as_str())
     }
 
     fn position(&self) -> Option<(usize, usize, usize)> {
@@ -231,3 +234,182 @@ impl Exception for Redefinition {
         Some((self.1.line, self.1.column.0, self.1.column.1))
     }
 }
+
+pub struct InvalidArgument(pub String);
+impl Exception for InvalidArgument {
+    fn error(&self) -> &'static str {
+        "Invalid Argument"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        None
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        None
+    }
+}
+
+pub struct AssertionFailed(pub String, pub Position);
+impl Exception for AssertionFailed {
+    fn error(&self) -> &'static str {
+        "Assertion Failed"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct Warning(pub String, pub Position);
+impl Exception for Warning {
+    fn level(&self) -> &'static str {
+        "Compiler Warning"
+    }
+
+    fn error(&self) -> &'static str {
+        "Warning"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct Info(pub String, pub Position);
+impl Exception for Info {
+    fn level(&self) -> &'static str {
+        "Compiler Info"
+    }
+
+    fn error(&self) -> &'static str {
+        "Info"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct MemoryOverlap(pub String, pub Position);
+impl Exception for MemoryOverlap {
+    fn error(&self) -> &'static str {
+        "Memory Overlap"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct SkipHazard(pub String, pub Position);
+impl Exception for SkipHazard {
+    fn error(&self) -> &'static str {
+        "Skip Hazard"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct ProgramTooLarge(pub String, pub Position);
+impl Exception for ProgramTooLarge {
+    fn error(&self) -> &'static str {
+        "Program Too Large"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct PrivateLabel(pub String, pub Position);
+impl Exception for PrivateLabel {
+    fn error(&self) -> &'static str {
+        "Private Label"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
+
+pub struct AmbiguousLabel(pub String, pub Position);
+impl Exception for AmbiguousLabel {
+    fn error(&self) -> &'static str {
+        "Ambiguous Label"
+    }
+
+    fn details(&self) -> &str {
+        &self.0
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(self.1.file.as_str())
+    }
+
+    fn position(&self) -> Option<(usize, usize, usize)> {
+        Some((self.1.line, self.1.column.0, self.1.column.1))
+    }
+}
@@ -0,0 +1,193 @@
+use crate::exception::{Position, Result, SyntaxError, Undefined};
+
+const OPERATORS: [&str; 20] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~",
+];
+
+/// Binary operators from the loosest to the tightest binding
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+#[derive(Debug, PartialEq)]
+enum ExprToken {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+    LParen,
+    RParen,
+}
+
+struct Evaluator<'a, F: Fn(&str) -> Option<i64>> {
+    tokens: Vec<ExprToken>,
+    current: usize,
+    position: &'a Position,
+    lookup: F,
+}
+
+/// Evaluates a constant expression such as `sprites_end - sprites <= 0x100`.
+/// Numbers follow the lexer's rules, reading ones without a prefix in `radix`,
+/// and symbols are resolved through `lookup`.
+pub fn evaluate(
+    expr: &str,
+    position: &Position,
+    radix: u32,
+    lookup: impl Fn(&str) -> Option<i64>,
+) -> Result<i64> {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expr, position, radix)?,
+        current: 0,
+        position,
+        lookup,
+    };
+    let value = evaluator.binary(0)?;
+    if evaluator.current != evaluator.tokens.len() {
+        return Err(evaluator.error("Unexpected token in expression"));
+    }
+    Ok(value)
+}
+
+pub fn parse_number(num: &str, radix: u32) -> Option<i64> {
+    let lower = num.to_ascii_lowercase();
+    let (digits, base) = match lower.as_bytes() {
+        [b'0', b'b', ..] => (&lower[2..], 2),
+        [b'0', b'd', ..] => (&lower[2..], 10),
+        [b'0', b'o', ..] => (&lower[2..], 8),
+        [b'0', b'x', ..] => (&lower[2..], 16),
+        _ => (lower.as_str(), radix),
+    };
+    i64::from_str_radix(digits, base).ok()
+}
+
+fn tokenize(expr: &str, position: &Position, radix: u32) -> Result<Vec<ExprToken>> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' {
+                ExprToken::LParen
+            } else {
+                ExprToken::RParen
+            });
+            rest = &rest[1..];
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            // Like the lexer, words made of hex digits are numbers rather than symbols
+            if c.is_ascii_digit() || (radix == 16 && word.chars().all(|c| c.is_ascii_hexdigit())) {
+                match parse_number(word, radix) {
+                    Some(n) => tokens.push(ExprToken::Number(n)),
+                    None => {
+                        return Err(Box::new(SyntaxError(
+                            format!("Invalid number '{}' in expression", word),
+                            position.clone(),
+                        )))
+                    }
+                }
+            } else {
+                tokens.push(ExprToken::Symbol(word.to_string()));
+            }
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(ExprToken::Operator(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(Box::new(SyntaxError(
+                format!("Invalid character '{}' in expression", c),
+                position.clone(),
+            )));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+impl<F: Fn(&str) -> Option<i64>> Evaluator<'_, F> {
+    fn error(&self, message: &str) -> Box<SyntaxError> {
+        Box::new(SyntaxError(message.to_string(), self.position.clone()))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(&ExprToken::Operator(op)) = self.tokens.get(self.current) {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.current += 1;
+            let right = self.binary(level + 1)?;
+            left = match op {
+                "||" => (left != 0 || right != 0) as i64,
+                "&&" => (left != 0 && right != 0) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "<<" | ">>" if !(0..64).contains(&right) => {
+                    return Err(self.error("Shift amount in expression must be from 0 to 63"))
+                }
+                "<<" => left << right,
+                ">>" => left >> right,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if right == 0 => return Err(self.error("Division by zero in expression")),
+                "/" => match left.checked_div(right) {
+                    Some(n) => n,
+                    None => return Err(self.error("Division overflows in expression")),
+                },
+                _ => match left.checked_rem(right) {
+                    Some(n) => n,
+                    None => return Err(self.error("Remainder overflows in expression")),
+                },
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64> {
+        let token = self.tokens.get(self.current);
+        self.current += 1;
+        match token {
+            Some(ExprToken::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(ExprToken::Operator("!")) => Ok((self.unary()? == 0) as i64),
+            Some(ExprToken::Operator("~")) => Ok(!self.unary()?),
+            Some(ExprToken::Number(n)) => Ok(*n),
+            Some(ExprToken::Symbol(s)) => match (self.lookup)(s) {
+                Some(n) => Ok(n),
+                None => Err(Box::new(Undefined(
+                    format!("'{}' is not defined anywhere", s),
+                    self.position.clone(),
+                ))),
+            },
+            Some(ExprToken::LParen) => {
+                let value = self.binary(0)?;
+                if self.tokens.get(self.current) != Some(&ExprToken::RParen) {
+                    return Err(self.error("Expected ')' in expression"));
+                }
+                self.current += 1;
+                Ok(value)
+            }
+            _ => Err(self.error("Expected a number, symbol or '(' in expression")),
+        }
+    }
+}
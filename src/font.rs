@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+/// Rows of each glyph in the built-in font
+const BUILTIN_HEIGHT: usize = 5;
+
+/// The built-in font, 4 pixels wide and 5 tall, in the high bits of each row
+const BUILTIN: [(char, [u8; BUILTIN_HEIGHT]); 48] = [
+    ('A', [0x60, 0x90, 0xF0, 0x90, 0x90]),
+    ('B', [0xE0, 0x90, 0xE0, 0x90, 0xE0]),
+    ('C', [0x70, 0x80, 0x80, 0x80, 0x70]),
+    ('D', [0xE0, 0x90, 0x90, 0x90, 0xE0]),
+    ('E', [0xF0, 0x80, 0xE0, 0x80, 0xF0]),
+    ('F', [0xF0, 0x80, 0xE0, 0x80, 0x80]),
+    ('G', [0x70, 0x80, 0xB0, 0x90, 0x70]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x30, 0x10, 0x10, 0x90, 0x60]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xE0, 0x90, 0xE0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xA0, 0x50]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0x60]),
+    ('V', [0x90, 0x90, 0x90, 0x60, 0x60]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    ('0', [0x60, 0xB0, 0x90, 0xD0, 0x60]),
+    ('1', [0x40, 0xC0, 0x40, 0x40, 0xE0]),
+    ('2', [0xE0, 0x10, 0x60, 0x80, 0xF0]),
+    ('3', [0xE0, 0x10, 0x60, 0x10, 0xE0]),
+    ('4', [0x90, 0x90, 0xF0, 0x10, 0x10]),
+    ('5', [0xF0, 0x80, 0xE0, 0x10, 0xE0]),
+    ('6', [0x60, 0x80, 0xE0, 0x90, 0x60]),
+    ('7', [0xF0, 0x10, 0x20, 0x40, 0x40]),
+    ('8', [0x60, 0x90, 0x60, 0x90, 0x60]),
+    ('9', [0x60, 0x90, 0x70, 0x10, 0x60]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x80]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    ('!', [0x80, 0x80, 0x80, 0x00, 0x80]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('+', [0x00, 0x40, 0xE0, 0x40, 0x00]),
+    (':', [0x00, 0x80, 0x00, 0x80, 0x00]),
+    ('\'', [0x80, 0x80, 0x00, 0x00, 0x00]),
+    ('/', [0x10, 0x20, 0x20, 0x40, 0x80]),
+    ('(', [0x40, 0x80, 0x80, 0x80, 0x40]),
+    (')', [0x80, 0x40, 0x40, 0x40, 0x80]),
+];
+
+/// Glyphs for drawing text, a byte per row
+pub struct Font {
+    pub height: usize,
+    glyphs: HashMap<char, Vec<u8>>,
+}
+
+impl Font {
+    pub fn builtin() -> Self {
+        Self {
+            height: BUILTIN_HEIGHT,
+            glyphs: BUILTIN
+                .iter()
+                .map(|(c, rows)| (*c, rows.to_vec()))
+                .collect(),
+        }
+    }
+
+    /// A font of `height` rows from each character's glyph
+    pub fn new(height: usize, glyphs: impl IntoIterator<Item = (char, Vec<u8>)>) -> Self {
+        Self {
+            height,
+            glyphs: glyphs.into_iter().collect(),
+        }
+    }
+
+    /// The rows of `c`, falling back on the other case when only one is drawn
+    pub fn glyph(&self, c: char) -> Option<&[u8]> {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.glyphs.get(&c.to_ascii_uppercase()))
+            .or_else(|| self.glyphs.get(&c.to_ascii_lowercase()))
+            .map(Vec::as_slice)
+    }
+
+    /// Pixels from the left edge to the rightmost one any glyph sets
+    pub fn width(&self) -> usize {
+        let used = self
+            .glyphs
+            .values()
+            .flatten()
+            .fold(0u8, |used, row| used | row);
+        8 - used.trailing_zeros().min(8) as usize
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+
+/// A netpbm file being read, header first
+struct Reader {
+    bytes: Vec<u8>,
+    at: usize,
+}
+
+impl Reader {
+    /// Skips whitespace and `#` comments
+    fn skip(&mut self) {
+        while let Some(&b) = self.bytes.get(self.at) {
+            match b {
+                b'#' => {
+                    while self.bytes.get(self.at).is_some_and(|&b| b != b'\n') {
+                        self.at += 1;
+                    }
+                }
+                _ if b.is_ascii_whitespace() => self.at += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<usize, String> {
+        self.skip();
+        let start = self.at;
+        while self.bytes.get(self.at).is_some_and(u8::is_ascii_digit) {
+            self.at += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.at])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| format!("Expected the {} of the image", what))
+    }
+
+    /// The bytes after the single whitespace that ends a binary header
+    fn raster(&self, len: usize) -> Result<&[u8], String> {
+        self.bytes
+            .get(self.at + 1..self.at + 1 + len)
+            .ok_or_else(|| String::from("The image ends before all of its pixels"))
+    }
+}
+
+/// Reads a PBM (`P1` or `P4`) or PGM (`P2` or `P5`) image as rows of pixels,
+/// where dark pixels are set. PGM pixels are set below half of the maximum value.
+pub fn load(path: &str) -> Result<Vec<Vec<bool>>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not read image '{}': {}", path, e))?;
+    let mut reader = Reader { bytes, at: 2 };
+    let format = match reader.bytes.get(..2) {
+        Some(b"P1") => 1,
+        Some(b"P2") => 2,
+        Some(b"P4") => 4,
+        Some(b"P5") => 5,
+        _ => {
+            return Err(format!(
+                "'{}' is not a PBM (P1, P4) or PGM (P2, P5) image",
+                path
+            ))
+        }
+    };
+    let width = reader.number("width")?;
+    let height = reader.number("height")?;
+    let max = match format {
+        2 | 5 => match reader.number("maximum value")? {
+            0 => return Err(String::from("The maximum value of the image can't be 0")),
+            max => max,
+        },
+        _ => 1,
+    };
+    // Set when darker than half way, for PBM that's every 1
+    let dark = |value: usize| value * 2 < max;
+
+    let pixels = match format {
+        1 => {
+            let mut pixels = Vec::with_capacity(width * height);
+            while pixels.len() < width * height {
+                reader.skip();
+                match reader.bytes.get(reader.at) {
+                    Some(b'0') => pixels.push(false),
+                    Some(b'1') => pixels.push(true),
+                    Some(_) => return Err(String::from("PBM pixels must be 0 or 1")),
+                    None => return Err(String::from("The image ends before all of its pixels")),
+                }
+                reader.at += 1;
+            }
+            pixels
+        }
+        2 => (0..width * height)
+            .map(|_| reader.number("next pixel").map(dark))
+            .collect::<Result<_, _>>()?,
+        4 => {
+            let stride = width.div_ceil(8);
+            let raster = reader.raster(stride * height)?;
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (y, x)))
+                .map(|(y, x)| raster[y * stride + x / 8] & (0x80 >> (x % 8)) != 0)
+                .collect()
+        }
+        _ => {
+            let size = if max > 0xFF { 2 } else { 1 };
+            reader
+                .raster(width * height * size)?
+                .chunks(size)
+                .map(|sample| dark(sample.iter().fold(0, |value, &b| value << 8 | b as usize)))
+                .collect()
+        }
+    };
+    Ok(pixels.chunks(width.max(1)).map(<[bool]>::to_vec).collect())
+}
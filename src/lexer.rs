@@ -1,22 +1,59 @@
 use crate::{
     exception::{
-        Exception, InvalidToken, NumberOverflow, Position, Result, SyntaxError, UnknownException,
+        Exception, InvalidToken, MprocessorException, NumberOverflow, Position, Result,
+        SyntaxError, UnknownException, Warning,
     },
-    token::{Command, MprocessorDirective, Token, TokenType},
+    lower::KEYWORDS,
+    octo,
+    token::{Command, Comparison, MprocessorDirective, Token, TokenType},
+};
+use std::{
+    collections::HashMap,
+    iter::{Enumerate, Peekable},
+    num::IntErrorKind,
+    rc::Rc,
+    str::Chars,
 };
-use std::{num::IntErrorKind, rc::Rc};
 
 const LITERALS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
 
-pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
+/// How labels the lexer lowercased were spelled, by where they are
+pub type Spellings = HashMap<Position, String>;
+
+/// How the lexer reads the source
+#[derive(Clone)]
+pub struct LexerOptions {
+    /// The base of numbers without a prefix, until `%radix` changes it
+    pub radix: u32,
+    /// Warn about numbers of more than one digit without a prefix
+    pub warn_bare_numbers: bool,
+    /// Keep labels as they are spelled instead of lowercasing them
+    pub case_sensitive: bool,
+}
+
+pub fn lex(
+    input: &str,
+    filename: Rc<String>,
+    options: &LexerOptions,
+    diagnostics: &mut Vec<Box<dyn Exception>>,
+    spellings: &mut Spellings,
+) -> Result<Vec<Token>> {
+    if filename.ends_with(".8o") {
+        return octo::import(input, filename, options);
+    }
     let mut tokens = Vec::new();
+    let mut radix = options.radix;
+    // How deep in %?# and %?! the lexer is, %radix can't be conditional
+    let mut conditionals = 0;
     let mut chars = input.chars().enumerate().peekable();
     let mut line = 1;
     let mut last_line = 0;
     let mut last = 0;
+    // Where a '-' right before a number started
+    let mut negative = None;
 
-    while let Some((j, mut c)) = chars.next() {
-        c = c.to_ascii_uppercase();
+    while let Some((j, raw)) = chars.next() {
+        let c = raw.to_ascii_uppercase();
         let i = j - last_line + 1;
         last = i + 1;
         match c {
@@ -38,6 +75,94 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                 line,
                 (i, i + 1),
             )),
+            '=' | '!' => match chars.next_if(|(_, c)| *c == '=') {
+                Some(_) => tokens.push(Token::new(
+                    TokenType::Comparison(if c == '=' {
+                        Comparison::Eq
+                    } else {
+                        Comparison::Ne
+                    }),
+                    Rc::clone(&filename),
+                    line,
+                    (i, i + 2),
+                )),
+                None => {
+                    return Err(Box::new(InvalidToken(
+                        format!("Expected '=' after '{}'", c),
+                        Position::new(line, (i, i + 1), Rc::clone(&filename)),
+                    )))
+                }
+            },
+            '<' | '>' => {
+                let equal = chars.next_if(|(_, c)| *c == '=').is_some();
+                tokens.push(Token::new(
+                    TokenType::Comparison(match (c, equal) {
+                        ('<', false) => Comparison::Lt,
+                        ('<', true) => Comparison::Le,
+                        (_, false) => Comparison::Gt,
+                        (_, true) => Comparison::Ge,
+                    }),
+                    Rc::clone(&filename),
+                    line,
+                    (i, i + 1 + equal as usize),
+                ))
+            }
+            '-' if chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => negative = Some(i),
+            '\'' | '"' => {
+                let quote = c;
+                let mut text = String::new();
+                let mut end = i + 1;
+                loop {
+                    let c = match chars.next() {
+                        Some((_, '\n')) | None => {
+                            return Err(Box::new(SyntaxError(
+                                format!("Expected a closing {} before the end of the line", quote),
+                                Position::new(line, (i, end), Rc::clone(&filename)),
+                            )))
+                        }
+                        Some((_, c)) if c == quote => break,
+                        Some((_, '\\')) => {
+                            end += 1;
+                            match chars.next().and_then(|(_, c)| escape(c)) {
+                                Some(c) => c,
+                                None => {
+                                    return Err(Box::new(SyntaxError(
+                                        "Invalid escape, expected \\n, \\t, \\0, \\\\, \\' or \\\""
+                                            .to_string(),
+                                        Position::new(
+                                            line,
+                                            (end - 1, end + 1),
+                                            Rc::clone(&filename),
+                                        ),
+                                    )))
+                                }
+                            }
+                        }
+                        Some((_, c)) => c,
+                    };
+                    end += 1;
+                    text.push(c);
+                }
+                end += 1;
+                let position = Position::new(line, (i, end), Rc::clone(&filename));
+                if !text.is_ascii() {
+                    return Err(Box::new(InvalidToken(
+                        format!("{}{}{} has characters outside of ASCII", quote, text, quote),
+                        position,
+                    )));
+                }
+                let token = if quote == '"' {
+                    TokenType::Str(text)
+                } else if text.len() == 1 {
+                    TokenType::Number(text.as_bytes()[0] as u16)
+                } else {
+                    return Err(Box::new(SyntaxError(
+                        format!("'{}' should be a single character", text),
+                        position,
+                    )));
+                };
+                tokens.push(Token { token, position });
+            }
             ';' => {
                 let mut word = String::new();
                 let start = i;
@@ -102,30 +227,39 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                     },
                     '?' => match chars.next() {
                         Some((_, c)) => match c {
-                            '#' => tokens.push(Token::new(
-                                TokenType::MprocessorDirective(MprocessorDirective::M_ifdef),
-                                Rc::clone(&filename),
-                                line,
-                                (i, i + 3),
-                            )),
-                            '!' => tokens.push(Token::new(
-                                TokenType::MprocessorDirective(MprocessorDirective::M_ifndef),
-                                Rc::clone(&filename),
-                                line,
-                                (i, i + 3),
-                            )),
+                            '#' => {
+                                conditionals += 1;
+                                tokens.push(Token::new(
+                                    TokenType::MprocessorDirective(MprocessorDirective::M_ifdef),
+                                    Rc::clone(&filename),
+                                    line,
+                                    (i, i + 3),
+                                ))
+                            }
+                            '!' => {
+                                conditionals += 1;
+                                tokens.push(Token::new(
+                                    TokenType::MprocessorDirective(MprocessorDirective::M_ifndef),
+                                    Rc::clone(&filename),
+                                    line,
+                                    (i, i + 3),
+                                ))
+                            }
                             '|' => tokens.push(Token::new(
                                 TokenType::MprocessorDirective(MprocessorDirective::M_else),
                                 Rc::clone(&filename),
                                 line,
                                 (i, i + 3),
                             )),
-                            '-' => tokens.push(Token::new(
-                                TokenType::MprocessorDirective(MprocessorDirective::M_endif),
-                                Rc::clone(&filename),
-                                line,
-                                (i, i + 3),
-                            )),
+                            '-' => {
+                                conditionals -= conditionals.min(1);
+                                tokens.push(Token::new(
+                                    TokenType::MprocessorDirective(MprocessorDirective::M_endif),
+                                    Rc::clone(&filename),
+                                    line,
+                                    (i, i + 3),
+                                ))
+                            }
                             _ => {
                                 return Err(Box::new(InvalidToken(
                                     format!("Invalid preprocessor directive '%{}'", c),
@@ -146,6 +280,97 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                         line,
                         (i, i + 2),
                     )),
+                    _ if c.is_ascii_alphabetic() => {
+                        let mut word = c.to_string();
+                        while let Some((_, c)) = chars.peek() {
+                            if !c.is_ascii_alphabetic() {
+                                break;
+                            }
+                            word.push(*c);
+                            chars.next();
+                        }
+                        let directive = match word.to_ascii_lowercase().as_str() {
+                            "warning" => MprocessorDirective::M_warning,
+                            "info" => MprocessorDirective::M_info,
+                            "assert" => MprocessorDirective::M_assert,
+                            "radix" => MprocessorDirective::M_radix,
+                            "export" => MprocessorDirective::M_export,
+                            _ => {
+                                return Err(Box::new(InvalidToken(
+                                    format!("Invalid preprocessor directive '%{}'", word),
+                                    Position::new(
+                                        line,
+                                        (i, i + 1 + word.len()),
+                                        Rc::clone(&filename),
+                                    ),
+                                )))
+                            }
+                        };
+                        tokens.push(Token::new(
+                            TokenType::MprocessorDirective(directive.clone()),
+                            Rc::clone(&filename),
+                            line,
+                            (i, i + 1 + word.len()),
+                        ));
+
+                        // Named directives take the rest of the line as their argument
+                        let mut arg = String::new();
+                        let start = i + 1 + word.len();
+                        let mut end = j + 2 + word.len();
+                        for (j, c) in chars.by_ref() {
+                            if c == '\n' {
+                                break;
+                            }
+                            end = j + 2;
+                            arg.push(c);
+                        }
+                        // The argument can follow a `;` like those of the other directives
+                        let arg = match arg.trim_start().strip_prefix(';') {
+                            Some(rest) => rest.to_string(),
+                            None => arg,
+                        };
+                        // Numbers are read before conditionals are resolved, so the lexer
+                        // applies %radix itself
+                        if directive == MprocessorDirective::M_radix {
+                            let position =
+                                Position::new(line, (i, end - last_line), Rc::clone(&filename));
+                            if conditionals > 0 {
+                                return Err(Box::new(MprocessorException(
+                                    "%radix applies to the rest of the file, so it can't be inside %?# or %?!".to_string(),
+                                    position,
+                                )));
+                            }
+                            radix = match arg.trim() {
+                                "2" => 2,
+                                "8" => 8,
+                                "10" => 10,
+                                "16" => 16,
+                                _ => {
+                                    return Err(Box::new(MprocessorException(
+                                        format!(
+                                            "Invalid radix '{}', expected 2, 8, 10 or 16",
+                                            arg.trim()
+                                        ),
+                                        position,
+                                    )))
+                                }
+                            };
+                        }
+                        tokens.push(Token::new(
+                            TokenType::Comment(arg),
+                            Rc::clone(&filename),
+                            line,
+                            (start, end - last_line),
+                        ));
+                        line += 1;
+                        last_line = end;
+                        tokens.push(Token::new(
+                            TokenType::Eol,
+                            Rc::clone(&filename),
+                            line,
+                            (end, end + 1),
+                        ));
+                    }
                     _ => {
                         return Err(Box::new(SyntaxError(
                             format!("Invalid preprocessor directive '{}'", c),
@@ -160,11 +385,12 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                     )));
                 }
             },
-            _ if c.is_digit(10) => {
+            _ if c.is_ascii_digit() => {
                 let mut num = c.to_string();
                 let start = i;
                 let mut end = i + 1;
-                let mut base = 16;
+                let mut base = radix;
+                let mut prefixed = true;
                 if c == '0' && chars.peek().is_some() {
                     match chars.peek().unwrap().1 {
                         'b' | 'B' => {
@@ -180,59 +406,123 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                             chars.next();
                         }
                         'x' | 'X' => {
+                            base = 16;
                             chars.next();
                         }
-                        _ => (),
+                        _ => prefixed = false,
                     }
+                } else {
+                    prefixed = false;
                 }
                 while let Some((i, c)) = chars.peek() {
-                    if !matches!(c, '0'..='9' | 'a'..='f' | 'A'..='F') {
+                    if !c.is_ascii_hexdigit() {
                         break;
                     }
-                    end = *i + 2;
+                    end = *i + 2 - last_line;
                     num.push(*c);
                     chars.next();
                 }
 
-                tokens.push(Token::new(
-                    TokenType::Number(match u16::from_str_radix(&num, base) {
-                        Ok(num) => num,
-                        Err(err) => {
-                            let pos = Position::new(line, (start, end), Rc::clone(&filename));
-                            let exception: Box<dyn Exception> = match err.kind() {
-                                IntErrorKind::InvalidDigit => Box::new(SyntaxError(
-                                    format!("Invalid digit found while parsing number '{}'", num),
-                                    pos,
-                                )),
-                                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => Box::new(
-                                    NumberOverflow(format!("Number '{}' is too large", num), pos),
+                let start = negative.take().unwrap_or(start);
+                // Only plain digits name an anonymous label, `1f:` or `0x10:` would
+                // never match how they are referred to
+                if defines_label(&chars)
+                    && (prefixed || start < i || !num.chars().all(|c| c.is_ascii_digit()))
+                {
+                    let spelling = input
+                        .chars()
+                        .skip(j - (i - start))
+                        .take(end - start)
+                        .collect::<String>();
+                    return Err(Box::new(SyntaxError(
+                        format!(
+                            "'{}' is a number, anonymous labels are written in decimal digits like '1:'",
+                            spelling
+                        ),
+                        Position::new(line, (start, end), Rc::clone(&filename)),
+                    )));
+                }
+                // `1b` and `1f` refer to anonymous labels, though they are hex numbers too
+                if let Some((label, forward)) =
+                    anonymous(&num, base).filter(|_| !prefixed && start == i)
+                {
+                    tokens.push(Token::new(
+                        TokenType::Anonymous(label, forward, u16::from_str_radix(&num, base).ok()),
+                        Rc::clone(&filename),
+                        line,
+                        (start, end),
+                    ));
+                    continue;
+                }
+                let value = match u16::from_str_radix(&num, base) {
+                    Ok(num) => num,
+                    Err(err) => {
+                        let pos = Position::new(line, (start, end), Rc::clone(&filename));
+                        let exception: Box<dyn Exception> = match err.kind() {
+                            IntErrorKind::InvalidDigit => Box::new(SyntaxError(
+                                format!("Invalid digit found while parsing number '{}'", num),
+                                pos,
+                            )),
+                            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => Box::new(
+                                NumberOverflow(format!("Number '{}' is too large", num), pos),
+                            ),
+                            _ => Box::new(UnknownException(
+                                format!(
+                                    "An unknown excpetion occured while parsing number '{}'",
+                                    num
                                 ),
-                                _ => Box::new(UnknownException(
-                                    format!(
-                                        "An unknown excpetion occured while parsing number '{}'",
-                                        num
-                                    ),
-                                    pos,
-                                )),
-                            };
-                            return Err(exception);
-                        }
-                    }),
+                                pos,
+                            )),
+                        };
+                        return Err(exception);
+                    }
+                };
+                if options.warn_bare_numbers && !prefixed && num.len() > 1 {
+                    diagnostics.push(bare_number(
+                        &num,
+                        radix,
+                        value,
+                        Position::new(line, (start, end), Rc::clone(&filename)),
+                    ));
+                }
+                tokens.push(Token::new(
+                    if start < i {
+                        TokenType::Negative(value)
+                    } else {
+                        TokenType::Number(value)
+                    },
                     Rc::clone(&filename),
                     line,
                     (start, end),
                 ));
             }
-            _ if LITERALS.contains(c) => {
+            _ if LITERALS.contains(c)
+                || c == '.' && chars.peek().is_some_and(|(_, c)| LITERALS.contains(*c)) =>
+            {
                 let mut word = c.to_string();
+                let mut spelling = raw.to_string();
                 let start = i;
-                let mut end = i + 1;
+                let mut end = j + 2;
                 while let Some((i, c)) = chars.peek() {
-                    if !(LITERALS.contains(*c) || c.is_numeric()) {
+                    // `gfx::draw_digit` is one qualified name
+                    if *c == ':' {
+                        let mut ahead = chars.clone().skip(1);
+                        match (ahead.next(), ahead.next()) {
+                            (Some((_, ':')), Some((_, c))) if LITERALS.contains(c) => {
+                                word.push_str("::");
+                                spelling.push_str("::");
+                                chars.nth(1);
+                                continue;
+                            }
+                            _ => break,
+                        }
+                    }
+                    if !(LITERALS.contains(*c) || c.is_numeric() || *c == '.') {
                         break;
                     }
                     end = *i + 2;
                     word.push(c.to_ascii_uppercase());
+                    spelling.push(*c);
                     chars.next();
                 }
                 end -= last_line;
@@ -254,28 +544,72 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
                             .map(TokenType::Command)
                     })
                     .or_else(|| {
-                        if let Ok(n) = u16::from_str_radix(&word, 16) {
-                            Some(TokenType::Number(n))
-                        } else {
-                            None
+                        // Words can only be numbers when hex digits are
+                        match u16::from_str_radix(&word, 16) {
+                            Ok(n) if radix == 16 => Some(TokenType::Number(n)),
+                            _ => None,
                         }
                     })
                     .or_else(|| {
-                        if word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
-                            Some(TokenType::Label(word.to_ascii_lowercase()))
+                        if word
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "_-.:".contains(c))
+                        {
+                            let label = word.to_ascii_lowercase();
+                            Some(TokenType::Label(
+                                if options.case_sensitive && !keyword(&label) {
+                                    spelling.clone()
+                                } else {
+                                    label
+                                },
+                            ))
                         } else {
                             None
                         }
                     })
                 {
-                    tokens.push(Token::new(token, Rc::clone(&filename), line, (start, end)));
+                    if matches!(token, TokenType::Number(_)) && defines_label(&chars) {
+                        return Err(Box::new(SyntaxError(
+                            format!("'{}' is a number, labels can't be hex words", spelling),
+                            Position::new(line, (start, end), Rc::clone(&filename)),
+                        )));
+                    }
+                    if let TokenType::Number(n) = token {
+                        if options.warn_bare_numbers && word.len() > 1 {
+                            diagnostics.push(bare_number(
+                                &word,
+                                radix,
+                                n,
+                                Position::new(line, (start, end), Rc::clone(&filename)),
+                            ));
+                        }
+                    }
+                    let token = Token::new(token, Rc::clone(&filename), line, (start, end));
+                    if matches!(&token.token, TokenType::Label(label) if *label != spelling) {
+                        spellings.insert(token.position.clone(), spelling);
+                    }
+                    tokens.push(token);
                 } else {
                     return Err(Box::new(InvalidToken(
-                        format!("Invalid token found while parsing '{}'", word),
+                        format!("Invalid token found while parsing '{}'", spelling),
                         Position::new(line, (start, end), Rc::clone(&filename)),
                     )));
                 }
             }
+            '#' | '.' => {
+                let mut row = c.to_string();
+                while let Some((_, c @ ('#' | '.'))) = chars.peek() {
+                    row.push(*c);
+                    chars.next();
+                }
+                let end = i + row.len();
+                tokens.push(Token::new(
+                    TokenType::Pixels(row),
+                    Rc::clone(&filename),
+                    line,
+                    (i, end),
+                ));
+            }
             _ => {
                 return Err(Box::new(InvalidToken(
                     format!("Invalid token found while parsing '{}'", c),
@@ -297,3 +631,55 @@ pub fn lex(input: &str, filename: Rc<String>) -> Result<Vec<Token>> {
     ));
     Ok(tokens)
 }
+
+/// Numbers without a prefix read differently in another radix, which is easy to miss
+fn bare_number(num: &str, radix: u32, value: u16, position: Position) -> Box<dyn Exception> {
+    Box::new(Warning(
+        format!(
+            "'{}' has no prefix, so it is read in base {} as 0x{:X}. Write 0x{:X} or 0d{} to be explicit",
+            num, radix, value, value, value
+        ),
+        position,
+    ))
+}
+
+/// Whether the word just read is followed by the `:` of a label definition, and
+/// not by the `::` of a qualified name
+fn defines_label(chars: &Peekable<Enumerate<Chars>>) -> bool {
+    let mut ahead = chars.clone();
+    match (ahead.next(), ahead.next()) {
+        (Some((_, ':')), Some((_, ':'))) => false,
+        (Some((_, ':')), _) => true,
+        _ => false,
+    }
+}
+
+/// The number of the anonymous label `num` refers to, and whether it looks forward
+fn anonymous(num: &str, base: u32) -> Option<(u16, bool)> {
+    let (digits, direction) = num.split_at(num.len() - 1);
+    let forward = match direction {
+        "f" | "F" => true,
+        "b" | "B" => false,
+        _ => return None,
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, base).ok().map(|n| (n, forward))
+}
+
+/// Words the lowering pass looks for, which stay lowercase so they work in any case
+fn keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word) || matches!(word, "then" | "flag")
+}
+
+/// The character a backslash escape stands for
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(c),
+        _ => None,
+    }
+}
@@ -0,0 +1,109 @@
+use std::{collections::HashMap, fs};
+
+use crate::{
+    exception::Position,
+    parser::Parser,
+    token::{Command, TokenType},
+};
+
+/// Words shown on each line of the listing, longer instructions continue below
+const WORDS_PER_LINE: usize = 2;
+
+/// What is at an address of the assembled program
+pub enum Row<'a> {
+    Label(&'a str),
+    /// The startup stub, of this many bytes
+    Stub(usize),
+    Instruction(&'a Command, &'a [TokenType], &'a Position),
+}
+
+/// Every label, the startup stub and every instruction by address. Labels go
+/// before the instruction at their address, and in name order among themselves.
+pub fn rows(parser: &Parser) -> Vec<(usize, Row<'_>)> {
+    let mut rows = Vec::new();
+    for (label, &address) in parser.symbols() {
+        rows.push((address, Row::Label(label)));
+    }
+    if let Some((start, end)) = parser.stub_region() {
+        rows.push((start, Row::Stub(end - start)));
+    }
+    for (address, cmd, args, position) in parser.instructions() {
+        rows.push((*address, Row::Instruction(cmd, args, position)));
+    }
+    rows.sort_by(|(a, first), (b, second)| {
+        a.cmp(b).then(match (first, second) {
+            (Row::Label(x), Row::Label(y)) => x.cmp(y),
+            (Row::Label(_), _) => std::cmp::Ordering::Less,
+            (_, Row::Label(_)) => std::cmp::Ordering::Greater,
+            _ => std::cmp::Ordering::Equal,
+        })
+    });
+    rows
+}
+
+/// Lays out the assembled program by address: every label, the bytes of every
+/// instruction, and the source line it came from. Pseudo-instructions and blocks
+/// show up as the instructions they expand to, under the line that wrote them.
+pub fn listing(parser: &Parser, rom: &[u8], load: u16) -> String {
+    let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+    let mut last_line = None;
+    let mut listing = String::new();
+    for (address, row) in rows(parser) {
+        let (size, text, source) = match row {
+            Row::Label(label) => {
+                listing.push_str(&format!("{:04X}  {:<9}  {}:\n", address, "", label));
+                continue;
+            }
+            Row::Stub(size) => (size, String::from("(startup stub)"), String::new()),
+            Row::Instruction(cmd, args, position) => {
+                let text = std::iter::once(format!("{:?}", cmd))
+                    .chain(args.iter().map(TokenType::to_string))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let line = (position.file.as_str(), position.line);
+                let source = if last_line == Some(line) {
+                    String::new()
+                } else {
+                    last_line = Some(line);
+                    let code = sources
+                        .entry(position.file.to_string())
+                        .or_insert_with(|| {
+                            fs::read_to_string(position.file.as_str())
+                                .map(|code| code.lines().map(str::to_string).collect())
+                                .unwrap_or_default()
+                        })
+                        .get(position.line - 1)
+                        .map(|code| code.trim())
+                        .unwrap_or_default();
+                    format!("; {}:{}  {}", position.file, position.line, code)
+                };
+                (cmd.size(args), text, source)
+            }
+        };
+        let offset = address - load as usize;
+        let words = rom[offset..offset + size]
+            .chunks(2)
+            .map(|word| {
+                word.iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        for (i, chunk) in words.chunks(WORDS_PER_LINE).enumerate() {
+            let words = chunk.join(" ");
+            if i == 0 {
+                listing.push_str(
+                    format!("{:04X}  {:<9}  {:<24}  {}", address, words, text, source).trim_end(),
+                );
+            } else {
+                listing.push_str(&format!(
+                    "{:04X}  {}",
+                    address + 2 * WORDS_PER_LINE * i,
+                    words
+                ));
+            }
+            listing.push('\n');
+        }
+    }
+    listing
+}
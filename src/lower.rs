@@ -0,0 +1,905 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    exception::{
+        Exception, NumberOverflow, Position, Redefinition, Result, SkipHazard, SyntaxError,
+        Undefined, Warning,
+    },
+    token::{Command, Comparison, Token, TokenType},
+};
+
+/// Words that start or end a block when they begin a statement. They are lexed as
+/// labels, so `loop:` can still be used as a label.
+pub const KEYWORDS: [&str; 6] = ["if", "else", "end", "loop", "again", "while"];
+
+type Statement = (Command, Vec<TokenType>);
+/// Register aliases by name, with where they were made
+type Aliases = HashMap<String, (u8, Position)>;
+
+enum Block {
+    If { id: usize, has_else: bool },
+    Loop { id: usize },
+    While { id: usize },
+}
+
+impl Block {
+    fn keyword(&self) -> &'static str {
+        match self {
+            Block::If { .. } => "if",
+            Block::Loop { .. } => "loop",
+            Block::While { .. } => "while",
+        }
+    }
+
+    fn closer(&self) -> &'static str {
+        match self {
+            Block::Loop { .. } => "again",
+            Block::If { .. } | Block::While { .. } => "end",
+        }
+    }
+}
+
+/// A condition as the instructions that set it up, followed by a skip that
+/// skips the next instruction when it holds
+struct Condition {
+    setup: Vec<Statement>,
+    skip: Statement,
+}
+
+struct Lowering {
+    output: Vec<Token>,
+    blocks: Vec<(Block, Position)>,
+    next_id: usize,
+    /// The register comparisons, `NOT` and `NEG` work in, `TEMP` changes it
+    temp: u8,
+    /// The skip the last instruction was, if it was one
+    after_skip: Option<Command>,
+    /// Aliases made in each open block
+    scopes: Vec<Aliases>,
+    /// Aliases made outside of blocks, by file
+    files: HashMap<String, Aliases>,
+    /// The last global label in each file, which local labels belong to
+    globals: HashMap<String, String>,
+    /// How many of each anonymous label each file has defined so far
+    anonymous: HashMap<(String, u16), usize>,
+    /// How many of each anonymous label each file defines in all
+    defined: HashMap<(String, u16), usize>,
+    warnings: Vec<Box<dyn Exception>>,
+}
+
+/// Rewrites `if`, `loop` and `while` blocks and pseudo-instructions into real
+/// instructions, leaving only statements the labels and statement passes understand.
+/// Blocks jump to generated labels such as `end@0`, which can never clash with a
+/// label in the source. Local labels become `global.local`, and anonymous labels
+/// are numbered by file.
+pub fn lower(tokens: Vec<Token>, diagnostics: &mut Vec<Box<dyn Exception>>) -> Result<Vec<Token>> {
+    let defined = definitions(&tokens);
+    let mut lowering = Lowering {
+        output: Vec::new(),
+        blocks: Vec::new(),
+        next_id: 0,
+        temp: 0xF,
+        after_skip: None,
+        scopes: Vec::new(),
+        files: HashMap::new(),
+        globals: HashMap::new(),
+        anonymous: HashMap::new(),
+        defined,
+        warnings: Vec::new(),
+    };
+    let mut line = Vec::new();
+    for token in tokens {
+        let end = matches!(token.token, TokenType::Eol | TokenType::Eof);
+        line.push(token);
+        if end {
+            lowering.line(mem::take(&mut line))?;
+        }
+    }
+    if let Some((block, position)) = lowering.blocks.pop() {
+        return Err(Box::new(SyntaxError(
+            format!(
+                "'{}' was never closed with '{}'",
+                block.keyword(),
+                block.closer()
+            ),
+            position,
+        )));
+    }
+    diagnostics.append(&mut lowering.warnings);
+    Ok(lowering.output)
+}
+
+impl Lowering {
+    fn line(&mut self, mut line: Vec<Token>) -> Result<()> {
+        // Labels before a keyword or pseudo-instruction stay where they are
+        let mut start = 0;
+        while let [Token {
+            token: TokenType::Label(_) | TokenType::Number(_),
+            ..
+        }, Token {
+            token: TokenType::Colon,
+            ..
+        }, ..] = &line[start..]
+        {
+            line[start].token = self.definition(&line[start])?;
+            start += 2;
+        }
+        // An alias can't stand for the name being aliased
+        let (command, from) = match line.get(start).map(|t| &t.token) {
+            Some(TokenType::Command(Command::ALIAS)) => (Some(Command::ALIAS), start + 2),
+            Some(TokenType::Command(command)) => (Some(command.clone()), start + 1),
+            _ => (None, start + 1),
+        };
+        for (index, token) in line.iter_mut().enumerate().skip(from) {
+            // Where a label can go, a name is always the label, even if it's also an alias
+            let label = command
+                .as_ref()
+                .is_some_and(|command| command.takes_label(index - start - 1));
+            if let TokenType::Label(name) = &token.token {
+                if let Some(register) = self.alias(name, &token.position).filter(|_| !label) {
+                    token.token = TokenType::Register(register);
+                } else if name.starts_with('.') {
+                    token.token = TokenType::Label(self.local(name, &token.position)?);
+                }
+            }
+        }
+        if let Some(Token {
+            token: TokenType::Command(command),
+            ..
+        }) = line.get(start)
+        {
+            let command = command.clone();
+            let mut args = line.split_off(start + 1);
+            self.references(&command, &mut args)?;
+            line.extend(literals(&command, args)?);
+        }
+        let (word, position) = match &line[start..] {
+            [Token {
+                token: TokenType::Label(word),
+                position,
+            }, rest @ ..]
+                if KEYWORDS.contains(&word.as_str())
+                    && rest.first().map(|t| &t.token) != Some(&TokenType::Colon) =>
+            {
+                (word.clone(), position.clone())
+            }
+            [Token {
+                token: TokenType::Command(command),
+                position,
+            }, ..]
+                if command.is_pseudo() =>
+            {
+                (format!("{:?}", command), position.clone())
+            }
+            [Token {
+                token: TokenType::Command(command),
+                ..
+            }, ..] => {
+                self.after_skip = Some(command.clone()).filter(Command::is_skip);
+                self.output.extend(line);
+                return Ok(());
+            }
+            _ => {
+                self.output.extend(line);
+                return Ok(());
+            }
+        };
+        let end = line.pop().unwrap();
+        let mut args = line
+            .drain(start..)
+            .filter(|t| !matches!(t.token, TokenType::Comment(_)))
+            .collect::<Vec<_>>();
+        let head = args.remove(0);
+        self.output.extend(line);
+
+        if let (Some(skip), true) = (&self.after_skip, KEYWORDS.contains(&word.as_str())) {
+            return Err(Box::new(SkipHazard(
+                format!(
+                    "{:?} only skips the first instruction of '{}', put the condition in an 'if' instead",
+                    skip, word
+                ),
+                position,
+            )));
+        }
+        if KEYWORDS.contains(&word.as_str()) {
+            self.after_skip = None;
+        }
+        match word.as_str() {
+            "if" => self.if_(args, &position)?,
+            "while" => {
+                let condition = self.condition(&args, &position, "while")?;
+                if args.len() > 3 {
+                    return Err(Self::unexpected(&args[3], "while"));
+                }
+                let id = self.id();
+                self.label(format!("loop@{}", id), &position);
+                self.condition_jump(condition, format!("end@{}", id), &position);
+                self.open(Block::While { id }, position);
+            }
+            "loop" => {
+                Self::no_args(&args, "loop")?;
+                let id = self.id();
+                self.label(format!("loop@{}", id), &position);
+                self.open(Block::Loop { id }, position);
+            }
+            "else" => {
+                Self::no_args(&args, "else")?;
+                match self.blocks.last_mut() {
+                    Some((Block::If { id, has_else }, _)) if !*has_else => {
+                        *has_else = true;
+                        let id = *id;
+                        self.jump(format!("end@{}", id), &position);
+                        self.label(format!("else@{}", id), &position);
+                    }
+                    Some((Block::If { .. }, opened)) => {
+                        return Err(Box::new(SyntaxError(
+                            format!("The 'if' at {} already has an 'else'", opened),
+                            position,
+                        )))
+                    }
+                    _ => {
+                        return Err(Box::new(SyntaxError(
+                            "'else' without an open 'if'".to_string(),
+                            position,
+                        )))
+                    }
+                }
+            }
+            "end" | "again" => self.close(&word, &args, position)?,
+            _ => {
+                let TokenType::Command(command) = head.token else {
+                    unreachable!()
+                };
+                let args = args.into_iter().map(|t| t.token).collect::<Vec<_>>();
+                let expansion = self.pseudo(command.clone(), &args, &position)?;
+                if expansion.len() > 1 {
+                    if let Some(skip) = &self.after_skip {
+                        return Err(Box::new(SkipHazard(
+                            format!(
+                                "{:?} only skips the first of the {} instructions {:?} expands to, use 'if ... then {:?}' instead",
+                                skip,
+                                expansion.len(),
+                                command,
+                                command
+                            ),
+                            position,
+                        )));
+                    }
+                }
+                if let Some((last, _)) = expansion.last() {
+                    self.after_skip = Some(last.clone()).filter(Command::is_skip);
+                }
+                for statement in expansion {
+                    self.emit(statement, &position);
+                }
+                self.output.push(end);
+                return Ok(());
+            }
+        }
+        self.output.push(end);
+        Ok(())
+    }
+
+    fn close(&mut self, word: &str, args: &[Token], position: Position) -> Result<()> {
+        Self::no_args(args, word)?;
+        let (block, opened) = match self.blocks.pop() {
+            Some(block) => {
+                self.scopes.pop();
+                block
+            }
+            None => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "'{}' without an open {}",
+                        word,
+                        if word == "end" {
+                            "'if' or 'while'"
+                        } else {
+                            "'loop'"
+                        }
+                    ),
+                    position,
+                )))
+            }
+        };
+        if block.closer() != word {
+            return Err(Box::new(SyntaxError(
+                format!(
+                    "The '{}' at {} must be closed with '{}', not '{}'",
+                    block.keyword(),
+                    opened,
+                    block.closer(),
+                    word
+                ),
+                position,
+            )));
+        }
+        match block {
+            Block::If { id, has_else } => self.label(
+                format!("{}@{}", if has_else { "end" } else { "else" }, id),
+                &position,
+            ),
+            Block::Loop { id } => self.jump(format!("loop@{}", id), &position),
+            Block::While { id } => {
+                self.jump(format!("loop@{}", id), &position);
+                self.label(format!("end@{}", id), &position);
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, block: Block, position: Position) {
+        self.blocks.push((block, position));
+        self.scopes.push(Aliases::new());
+    }
+
+    /// `if COND then` opens a block, `if COND then INSTRUCTION` only guards that
+    /// one instruction
+    fn if_(&mut self, args: Vec<Token>, position: &Position) -> Result<()> {
+        let condition = self.condition(&args, position, "if")?;
+        let mut args = args;
+        if let Some(Token {
+            token: TokenType::Command(command),
+            ..
+        }) = args.get(4)
+        {
+            let command = command.clone();
+            let mut rest = args.split_off(5);
+            self.references(&command, &mut rest)?;
+            args.extend(literals(&command, rest)?);
+        }
+        match args.get(3).map(|t| &t.token) {
+            Some(TokenType::Label(then)) if then == "then" => (),
+            Some(_) => return Err(Self::unexpected(&args[3], "if")),
+            None => {
+                return Err(Box::new(SyntaxError(
+                    "Expected 'then' after the condition".to_string(),
+                    position.clone(),
+                )))
+            }
+        }
+        match &args[4..] {
+            [] => {
+                let id = self.id();
+                self.condition_jump(condition, format!("else@{}", id), position);
+                self.open(
+                    Block::If {
+                        id,
+                        has_else: false,
+                    },
+                    position.clone(),
+                );
+            }
+            [Token {
+                token: TokenType::Command(command),
+                position: instruction,
+            }, rest @ ..]
+                if command.is_pseudo() =>
+            {
+                let rest = rest.iter().map(|t| t.token.clone()).collect::<Vec<_>>();
+                let expansion = self.pseudo(command.clone(), &rest, instruction)?;
+                if let Some((last, _)) = expansion.last() {
+                    self.after_skip = Some(last.clone()).filter(Command::is_skip);
+                }
+                if expansion.len() > 1 {
+                    // Too long to skip, so jump over it instead
+                    let id = self.id();
+                    self.condition_jump(condition, format!("end@{}", id), position);
+                    for statement in expansion {
+                        self.emit(statement, position);
+                    }
+                    self.label(format!("end@{}", id), position);
+                } else if expansion.is_empty() {
+                    return Err(Box::new(SyntaxError(
+                        format!(
+                            "{:?} takes effect while assembling, so it can't be conditional",
+                            command
+                        ),
+                        instruction.clone(),
+                    )));
+                } else {
+                    let (skip, skip_args) = condition.skip;
+                    for statement in condition
+                        .setup
+                        .into_iter()
+                        .chain([(skip.inverse_skip(), skip_args)])
+                        .chain(expansion)
+                    {
+                        self.emit(statement, position);
+                    }
+                }
+            }
+            [Token {
+                token: TokenType::Command(command),
+                ..
+            }, ..] => {
+                self.after_skip = Some(command.clone()).filter(Command::is_skip);
+                let (mut setup, (skip, skip_args)) = (condition.setup, condition.skip);
+                setup.push((skip.inverse_skip(), skip_args));
+                for statement in setup {
+                    self.emit(statement, position);
+                }
+                self.output.extend(args.into_iter().skip(4));
+                self.output.push(Token {
+                    token: TokenType::Eol,
+                    position: position.clone(),
+                });
+            }
+            [token, ..] => {
+                return Err(Box::new(SyntaxError(
+                    "Expected an instruction or the end of the line after 'then'".to_string(),
+                    token.position.clone(),
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `vX == NN`, `vX < vY` and the like
+    fn condition(&self, args: &[Token], position: &Position, keyword: &str) -> Result<Condition> {
+        // The operands are compared as bytes
+        let operands = literals(&Command::JMPEQ, args.iter().take(3).cloned().collect())?;
+        match operands.as_slice() {
+            [Token {
+                token: left @ TokenType::Register(_),
+                ..
+            }, Token {
+                token: TokenType::Comparison(comparison),
+                ..
+            }, Token {
+                token: right @ (TokenType::Register(_) | TokenType::Number(0..=0xFF)),
+                ..
+            }, ..] => {
+                let operands = vec![left.clone(), right.clone()];
+                Ok(match comparison {
+                    Comparison::Eq => Condition {
+                        setup: Vec::new(),
+                        skip: (Command::JMPEQ, operands),
+                    },
+                    Comparison::Ne => Condition {
+                        setup: Vec::new(),
+                        skip: (Command::JMPNE, operands),
+                    },
+                    _ => {
+                        let (setup, holds) =
+                            self.compare(*comparison, left, right, position, keyword)?;
+                        Condition {
+                            setup,
+                            skip: (
+                                Command::JMPEQ,
+                                vec![TokenType::Register(0xF), TokenType::Number(holds)],
+                            ),
+                        }
+                    }
+                })
+            }
+            _ => Err(Box::new(SyntaxError(
+                format!(
+                    "Expected a condition such as 'vX == NN' or 'vX < vY' after '{}'",
+                    keyword
+                ),
+                position.clone(),
+            ))),
+        }
+    }
+
+    /// CHIP-8 can only skip on equality, so `x < y` and the like subtract the
+    /// operands in the temp register and look at the borrow `SUB` and `SUBFROM`
+    /// leave in vF. Returns the instructions and the value of vF when it holds.
+    fn compare(
+        &self,
+        comparison: Comparison,
+        x: &TokenType,
+        y: &TokenType,
+        position: &Position,
+        name: &str,
+    ) -> Result<(Vec<Statement>, u16)> {
+        // vF is 1 when a >= b
+        let (a, b, holds) = match comparison {
+            Comparison::Lt => (x, y, 0),
+            Comparison::Ge => (x, y, 1),
+            Comparison::Gt => (y, x, 0),
+            Comparison::Le => (y, x, 1),
+            Comparison::Eq | Comparison::Ne => unreachable!(),
+        };
+        // The operand read after the temp register is written can't be the temp register
+        let setup = match b {
+            TokenType::Number(_) => {
+                let temp = self.temp(a, name, position)?;
+                vec![
+                    (Command::SET, vec![temp.clone(), b.clone()]),
+                    (Command::SUBFROM, vec![temp, a.clone()]),
+                ]
+            }
+            _ => {
+                let temp = self.temp(b, name, position)?;
+                vec![
+                    (Command::SET, vec![temp.clone(), a.clone()]),
+                    (Command::SUB, vec![temp, b.clone()]),
+                ]
+            }
+        };
+        Ok((setup, holds))
+    }
+
+    /// The temp register, as long as `operand` isn't it
+    fn temp(&self, operand: &TokenType, name: &str, position: &Position) -> Result<TokenType> {
+        let temp = TokenType::Register(self.temp);
+        if *operand == temp {
+            return Err(Box::new(SyntaxError(
+                format!(
+                    "'{}' works in v{:X}, so it can't also use v{:X} itself. Pick another temp register with TEMP",
+                    name, self.temp, self.temp
+                ),
+                position.clone(),
+            )));
+        }
+        Ok(temp)
+    }
+
+    /// The real instructions a pseudo-instruction stands for
+    fn pseudo(
+        &mut self,
+        command: Command,
+        args: &[TokenType],
+        position: &Position,
+    ) -> Result<Vec<Statement>> {
+        if let (Command::ALIAS, [TokenType::Number(n), ..]) = (&command, args) {
+            return Err(Box::new(SyntaxError(
+                format!(
+                    "Alias names can't be made of hex digits only, this one reads as 0x{:X}",
+                    n
+                ),
+                position.clone(),
+            )));
+        }
+        if !command.is_valid_chip8_instruction(args) {
+            return Err(Box::new(SyntaxError(
+                format!("Invalid arguments to command {:?}", command),
+                position.clone(),
+            )));
+        }
+        let name = format!("{:?}", command);
+        Ok(match (command, args) {
+            (Command::TEMP, &[TokenType::Register(r)]) => {
+                self.temp = r;
+                Vec::new()
+            }
+            (Command::ALIAS, [TokenType::Label(name), TokenType::Register(r), rest @ ..]) => {
+                self.define_alias(name, *r, rest, position)?;
+                Vec::new()
+            }
+            (Command::INC, [x]) => vec![(Command::ADD, vec![x.clone(), TokenType::Number(1)])],
+            (Command::DEC, [x]) => vec![(Command::ADD, vec![x.clone(), TokenType::Number(0xFF)])],
+            (Command::NOT, [x]) => {
+                let temp = self.temp(x, &name, position)?;
+                vec![
+                    (Command::SET, vec![temp.clone(), TokenType::Number(0xFF)]),
+                    (Command::XOR, vec![x.clone(), temp]),
+                ]
+            }
+            (Command::NEG, [x]) => {
+                let temp = self.temp(x, &name, position)?;
+                vec![
+                    (Command::SET, vec![temp.clone(), TokenType::Number(0)]),
+                    (Command::SUBFROM, vec![x.clone(), temp]),
+                ]
+            }
+            (Command::SKIP, []) => vec![(
+                Command::JMPEQ,
+                vec![TokenType::Register(0), TokenType::Register(0)],
+            )],
+            (comparison, [x, y, target]) => {
+                let comparison = match comparison {
+                    Command::JMPLT => Comparison::Lt,
+                    Command::JMPGE => Comparison::Ge,
+                    Command::JMPGT => Comparison::Gt,
+                    _ => Comparison::Le,
+                };
+                let (mut setup, holds) = self.compare(comparison, x, y, position, &name)?;
+                setup.push((
+                    Command::JMPNE,
+                    vec![TokenType::Register(0xF), TokenType::Number(holds)],
+                ));
+                setup.push((Command::JMP, vec![target.clone()]));
+                setup
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    /// The register `name` is an alias for, looking through the open blocks then
+    /// the file
+    fn alias(&self, name: &str, position: &Position) -> Option<u8> {
+        self.scopes
+            .iter()
+            .rev()
+            .chain(self.files.get(position.file.as_str()))
+            .find_map(|aliases| aliases.get(name))
+            .map(|&(register, _)| register)
+    }
+
+    fn define_alias(
+        &mut self,
+        name: &str,
+        register: u8,
+        rest: &[TokenType],
+        position: &Position,
+    ) -> Result<()> {
+        match rest {
+            [] if register == 0xF => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "vF is the flag register, which arithmetic, shifts and comparisons overwrite. Write 'ALIAS {} vF flag' if '{}' is meant to be the flag",
+                        name, name
+                    ),
+                    position.clone(),
+                )))
+            }
+            [] => (),
+            [TokenType::Label(flag)] if flag == "flag" && register == 0xF => (),
+            _ => {
+                return Err(Box::new(SyntaxError(
+                    "Only aliases for vF can end with 'flag'".to_string(),
+                    position.clone(),
+                )))
+            }
+        }
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => self.files.entry(position.file.to_string()).or_default(),
+        };
+        if let Some((old, defined)) = scope.get(name) {
+            return Err(Box::new(Redefinition(
+                format!(
+                    "'{}' is already an alias for v{:X} from {}",
+                    name, old, defined
+                ),
+                position.clone(),
+            )));
+        }
+        if let Some((other, (_, defined))) = scope.iter().find(|(_, (r, _))| *r == register) {
+            return Err(Box::new(Redefinition(
+                format!(
+                    "v{:X} is already aliased as '{}' from {}",
+                    register, other, defined
+                ),
+                position.clone(),
+            )));
+        }
+        scope.insert(name.to_string(), (register, position.clone()));
+        Ok(())
+    }
+
+    /// The label a definition at the start of a line really defines: local labels
+    /// join the global label before them, and `1:` gets a name for each time it's used
+    fn definition(&mut self, token: &Token) -> Result<TokenType> {
+        let file = token.position.file.to_string();
+        Ok(TokenType::Label(match &token.token {
+            TokenType::Label(name) if name.starts_with('.') => self.local(name, &token.position)?,
+            TokenType::Label(name) => {
+                self.globals.insert(file, name.clone());
+                name.clone()
+            }
+            TokenType::Number(n) => {
+                let count = self.anonymous.entry((file, *n)).or_default();
+                *count += 1;
+                anonymous(*n, *count - 1, &token.position)
+            }
+            _ => unreachable!(),
+        }))
+    }
+
+    /// `.name` under the last global label of its file
+    fn local(&self, name: &str, position: &Position) -> Result<String> {
+        match self.globals.get(position.file.as_str()) {
+            Some(global) => Ok(format!("{}{}", global, name)),
+            None => Err(Box::new(SyntaxError(
+                format!(
+                    "Local label '{}' needs a global label before it in this file",
+                    name
+                ),
+                position.clone(),
+            ))),
+        }
+    }
+
+    /// Turns `1b` and `1f` into the anonymous label they refer to, wherever
+    /// `command` takes a label. Anywhere else, or when there is no such label,
+    /// they are numbers.
+    fn references(&mut self, command: &Command, args: &mut [Token]) -> Result<()> {
+        let mut index = 0;
+        for arg in args {
+            match arg.token {
+                TokenType::Comment(_) | TokenType::Eol | TokenType::Eof => continue,
+                TokenType::Anonymous(n, forward, number) if command.takes_label(index) => {
+                    let key = (arg.position.file.to_string(), n);
+                    let count = self.anonymous.get(&key).copied().unwrap_or(0);
+                    let nth = match forward {
+                        true if count < self.defined.get(&key).copied().unwrap_or(0) => Some(count),
+                        false if count > 0 => Some(count - 1),
+                        _ => None,
+                    };
+                    let direction = if forward { "after" } else { "before" };
+                    match (nth, number) {
+                        (Some(nth), _) => {
+                            arg.token = TokenType::Label(anonymous(n, nth, &arg.position))
+                        }
+                        // `1f` was the address 0x1F before there were anonymous labels,
+                        // and stays that in code that doesn't use them
+                        (None, Some(number)) => self.warnings.push(Box::new(Warning(
+                            format!(
+                                "'{}' is the address 0x{:X}, as there is no anonymous label '{:X}:' {} it. Write 0x{:X} if that is meant",
+                                arg.token, number, n, direction, number
+                            ),
+                            arg.position.clone(),
+                        ))),
+                        (None, None) => {
+                            return Err(Box::new(Undefined(
+                                format!(
+                                    "There is no anonymous label '{:X}:' {} this",
+                                    n, direction
+                                ),
+                                arg.position.clone(),
+                            )))
+                        }
+                    }
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Jumps to `label` unless the condition holds
+    fn condition_jump(&mut self, condition: Condition, label: String, position: &Position) {
+        for statement in condition.setup {
+            self.emit(statement, position);
+        }
+        self.emit(condition.skip, position);
+        self.jump(label, position);
+    }
+
+    fn no_args(args: &[Token], keyword: &str) -> Result<()> {
+        match args.first() {
+            Some(token) => Err(Self::unexpected(token, keyword)),
+            None => Ok(()),
+        }
+    }
+
+    fn unexpected(token: &Token, keyword: &str) -> Box<SyntaxError> {
+        Box::new(SyntaxError(
+            format!("Unexpected '{}' in '{}'", token.token, keyword),
+            token.position.clone(),
+        ))
+    }
+
+    fn id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn emit(&mut self, (command, args): Statement, position: &Position) {
+        self.statement(
+            std::iter::once(TokenType::Command(command))
+                .chain(args)
+                .collect(),
+            position,
+        );
+    }
+
+    fn jump(&mut self, label: String, position: &Position) {
+        self.emit((Command::JMP, vec![TokenType::Label(label)]), position);
+    }
+
+    fn label(&mut self, label: String, position: &Position) {
+        self.statement(vec![TokenType::Label(label), TokenType::Colon], position);
+    }
+
+    fn statement(&mut self, tokens: Vec<TokenType>, position: &Position) {
+        for token in tokens.into_iter().chain([TokenType::Eol]) {
+            self.output.push(Token {
+                token,
+                position: position.clone(),
+            });
+        }
+    }
+}
+
+/// Turns negative numbers into the two's complement of their field, and strings
+/// into a number per character
+fn literals(command: &Command, args: Vec<Token>) -> Result<Vec<Token>> {
+    let mut literals = Vec::new();
+    let mut index = 0;
+    for arg in args {
+        match arg.token {
+            TokenType::Negative(n) => match command.signed_bits(index) {
+                Some(bits) => literals.push(Token {
+                    token: TokenType::Number(negative(n, bits, &arg.position)?),
+                    position: arg.position,
+                }),
+                None => {
+                    return Err(Box::new(SyntaxError(
+                        format!("{:?} can't take a negative number here", command),
+                        arg.position,
+                    )))
+                }
+            },
+            TokenType::Str(ref string) if *command == Command::BYTE => {
+                literals.extend(string.bytes().map(|b| Token {
+                    token: TokenType::Number(b as u16),
+                    position: arg.position.clone(),
+                }))
+            }
+            TokenType::Str(_) => {
+                return Err(Box::new(SyntaxError(
+                    "Strings can only be used as BYTE data".to_string(),
+                    arg.position,
+                )))
+            }
+            TokenType::Anonymous(_, _, Some(n)) => literals.push(Token {
+                token: TokenType::Number(n),
+                position: arg.position,
+            }),
+            TokenType::Anonymous(..) => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "'{}' refers to an anonymous label, but {:?} can't take a label here",
+                        arg.token, command
+                    ),
+                    arg.position,
+                )))
+            }
+            TokenType::Comment(_) | TokenType::Eol | TokenType::Eof => {
+                literals.push(arg);
+                continue;
+            }
+            _ => literals.push(arg),
+        }
+        index += 1;
+    }
+    Ok(literals)
+}
+
+/// How many times each file defines each anonymous label, found the same way
+/// `Lowering::line` finds definitions, so `1f` knows if there is a `1:` ahead
+fn definitions(tokens: &[Token]) -> HashMap<(String, u16), usize> {
+    let mut defined = HashMap::new();
+    let mut line_start = true;
+    for pair in tokens.windows(2) {
+        match (&pair[0].token, &pair[1].token) {
+            (TokenType::Eol | TokenType::Eof, _) => line_start = true,
+            (TokenType::Number(n), TokenType::Colon) if line_start => {
+                *defined
+                    .entry((pair[0].position.file.to_string(), *n))
+                    .or_default() += 1
+            }
+            (TokenType::Label(_) | TokenType::Number(_), TokenType::Colon)
+            | (TokenType::Colon, _)
+                if line_start => {}
+            _ => line_start = false,
+        }
+    }
+    defined
+}
+
+/// The name of the `index`th `n:` in the file, unique across files
+fn anonymous(n: u16, index: usize, position: &Position) -> String {
+    format!("{:X}@{}@{}", n, index, position.file)
+}
+
+/// `-n` in two's complement, as long as it fits in `bits`
+fn negative(n: u16, bits: u32, position: &Position) -> Result<u16> {
+    let lowest = 1u32 << (bits - 1);
+    if n as u32 > lowest {
+        return Err(Box::new(NumberOverflow(
+            format!(
+                "-0x{:X} does not fit in {} bits, which only go down to -0x{:X}",
+                n, bits, lowest
+            ),
+            position.clone(),
+        )));
+    }
+    Ok(((1u32 << bits) - n as u32) as u16 & ((1u32 << bits) - 1) as u16)
+}
@@ -1,35 +1,107 @@
-use std::{fs, rc::Rc};
+use std::{env, fs, rc::Rc};
 
 mod exception;
+mod expr;
+mod font;
+mod image;
 mod intruction;
 mod lexer;
+mod listing;
+mod lower;
+mod namespace;
+mod octo;
+mod options;
+mod output;
 mod parser;
+mod sprite;
 mod token;
 
-use exception::Exception;
+use exception::{Exception, InvalidArgument};
 use intruction::convert_to_chip8;
+use lexer::Spellings;
+use options::Options;
+use token::to_source;
 
 fn main() {
-    match run() {
-        Ok(v) => {
-            v.iter().for_each(|i| print!("{i:0>4X}"));
+    let options = match Options::from_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => return println!("{}", e),
+    };
+    if let Err(e) = run(&options) {
+        println!("{}", e);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Exception>> {
+    let code = fs::read_to_string(&options.input).map_err(|e| {
+        Box::new(InvalidArgument(format!(
+            "Could not read file '{}': {}",
+            options.input, e
+        ))) as Box<dyn Exception>
+    })?;
+    let mut diagnostics = Vec::new();
+    let mut spellings = Spellings::new();
+    let tokens = lexer::lex(
+        &code,
+        Rc::new(options.input.clone()),
+        &options.lexer,
+        &mut diagnostics,
+        &mut spellings,
+    );
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    let mut parser = parser::Parser::new(tokens?, spellings, options);
+    let instructions = if options.preprocess_only {
+        parser.preprocess().map(|_| None)
+    } else {
+        parser.parse().map(Some)
+    };
+    for diagnostic in parser.diagnostics() {
+        eprintln!("{}", diagnostic);
+    }
+
+    match instructions? {
+        None => {
+            let source = to_source(parser.tokens());
+            match &options.output {
+                Some(output) => write(output, source),
+                None => {
+                    print!("{}", source);
+                    Ok(())
+                }
+            }
+        }
+        Some(instructions) => {
+            let rom = convert_to_chip8(instructions, options.load);
+            rom.iter().for_each(|b| print!("{b:0>2X}"));
             println!();
-            fs::write(
-                "ibm.ch8",
-                v.iter()
-                    .flat_map(|i| [(i >> 8) as u8, (i & 0xFF) as u8].into_iter())
-                    .collect::<Vec<_>>(),
+            if let Some(path) = &options.listing {
+                write(path, listing::listing(&parser, &rom, options.load))?;
+            }
+            if let Some(path) = &options.octo {
+                write(path, octo::export(&parser, &rom, options.load))?;
+            }
+            if let Some(path) = &options.octo_options {
+                write(path, octo::options(&parser, options))?;
+            }
+            let path = options
+                .output
+                .as_deref()
+                .unwrap_or(options.format.default_output());
+            write(
+                path,
+                output::output(&rom, options.load, options.format, path),
             )
-            .unwrap();
         }
-        Err(e) => println!("{}", e),
     }
 }
 
-fn run() -> Result<Vec<u16>, Box<dyn Exception>> {
-    let tokens = lexer::lex(
-        fs::read_to_string("example.chasm").unwrap().as_str(),
-        Rc::new(String::from("example.chasm")),
-    )?;
-    Ok(convert_to_chip8(parser::Parser::new(tokens).parse()?))
+fn write(path: &str, contents: impl AsRef<[u8]>) -> Result<(), Box<dyn Exception>> {
+    fs::write(path, contents).map_err(|e| {
+        Box::new(InvalidArgument(format!(
+            "Could not write file '{}': {}",
+            path, e
+        ))) as Box<dyn Exception>
+    })
 }
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    exception::{AmbiguousLabel, Position, PrivateLabel, Result, SyntaxError, Undefined},
+    token::{Token, TokenType},
+};
+
+/// Files included with `%+ ; file as name`, and the labels they `%export`
+#[derive(Default)]
+pub struct Namespaces {
+    /// The namespace each file was included into
+    pub files: HashMap<String, String>,
+    /// The labels each file exports, with where it exported them
+    pub exports: HashMap<String, Vec<(String, Position)>>,
+}
+
+impl Namespaces {
+    /// Puts every label defined in a namespaced file under its namespace, as
+    /// `gfx::draw_digit`, and points every reference at the label it means. A
+    /// reference looks in its own namespace first, then at labels outside of
+    /// any namespace, then at what the namespaces export.
+    pub fn resolve(&self, mut tokens: Vec<Token>) -> Result<Vec<Token>> {
+        if self.files.is_empty() {
+            return Ok(tokens);
+        }
+        let mut members: HashMap<&str, HashSet<String>> = HashMap::new();
+        let mut globals = HashSet::new();
+        for namespace in self.files.values() {
+            members.entry(namespace).or_default();
+        }
+        for (i, token) in tokens.iter().enumerate() {
+            let TokenType::Label(name) = &token.token else {
+                continue;
+            };
+            if !definition(&tokens, i) || generated(name) {
+                continue;
+            }
+            if name.contains("::") {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "'{}' can't be defined with a namespace, include its file with 'as' instead",
+                        name
+                    ),
+                    token.position.clone(),
+                )));
+            }
+            match self.files.get(token.position.file.as_str()) {
+                Some(namespace) => members.get_mut(namespace.as_str()).unwrap(),
+                None => &mut globals,
+            }
+            .insert(name.clone());
+        }
+
+        let mut exports: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for (file, names) in &self.exports {
+            let Some(namespace) = self.files.get(file) else {
+                continue;
+            };
+            for (name, position) in names {
+                if !members[namespace.as_str()].contains(name) {
+                    return Err(Box::new(Undefined(
+                        format!(
+                            "'{}' is exported from {} but never defined",
+                            name, namespace
+                        ),
+                        position.clone(),
+                    )));
+                }
+                exports.entry(namespace).or_default().insert(name);
+            }
+        }
+
+        for i in 0..tokens.len() {
+            let TokenType::Label(name) = &tokens[i].token else {
+                continue;
+            };
+            if generated(name) {
+                continue;
+            }
+            let position = &tokens[i].position;
+            let own = self.files.get(position.file.as_str()).map(String::as_str);
+            let name = if definition(&tokens, i) {
+                match own {
+                    Some(namespace) => format!("{}::{}", namespace, name),
+                    None => continue,
+                }
+            } else {
+                let exported = |namespace: &str, name: &str| {
+                    exports
+                        .get(namespace)
+                        .is_some_and(|names| names.contains(name))
+                };
+                if let Some((namespace, label)) = name.split_once("::") {
+                    match members.get(namespace) {
+                        None => {
+                            return Err(Box::new(Undefined(
+                                format!("There is no namespace '{}'", namespace),
+                                position.clone(),
+                            )))
+                        }
+                        Some(labels) if !labels.contains(label) => {
+                            return Err(Box::new(Undefined(
+                                format!("{} has no label '{}'", namespace, label),
+                                position.clone(),
+                            )))
+                        }
+                        _ if own != Some(namespace) && !exported(namespace, label) => {
+                            return Err(private(label, namespace, position))
+                        }
+                        _ => continue,
+                    }
+                }
+                if let Some(namespace) = own.filter(|namespace| members[namespace].contains(name)) {
+                    format!("{}::{}", namespace, name)
+                } else if globals.contains(name) {
+                    continue;
+                } else {
+                    let mut exporters = members
+                        .keys()
+                        .filter(|namespace| exported(namespace, name))
+                        .collect::<Vec<_>>();
+                    exporters.sort();
+                    match exporters.as_slice() {
+                        [namespace] => format!("{}::{}", namespace, name),
+                        [] => match members.iter().find(|(_, labels)| labels.contains(name)) {
+                            Some((namespace, _)) => return Err(private(name, namespace, position)),
+                            // The labels pass reports it as undefined
+                            None => continue,
+                        },
+                        _ => {
+                            return Err(Box::new(AmbiguousLabel(
+                                format!(
+                                    "'{}' is exported by more than one namespace, write {}",
+                                    name,
+                                    exporters
+                                        .iter()
+                                        .map(|namespace| format!("{}::{}", namespace, name))
+                                        .collect::<Vec<_>>()
+                                        .join(" or ")
+                                ),
+                                position.clone(),
+                            )))
+                        }
+                    }
+                }
+            };
+            tokens[i].token = TokenType::Label(name);
+        }
+        Ok(tokens)
+    }
+}
+
+fn private(label: &str, namespace: &str, position: &Position) -> Box<PrivateLabel> {
+    Box::new(PrivateLabel(
+        format!(
+            "'{}' is private to {}, add it to the %export line of {} to use it here",
+            label, namespace, namespace
+        ),
+        position.clone(),
+    ))
+}
+
+/// Whether the label at `i` is being defined rather than referred to
+fn definition(tokens: &[Token], i: usize) -> bool {
+    matches!(tokens.get(i + 1).map(|t| &t.token), Some(TokenType::Colon))
+}
+
+/// Labels made by lowering blocks and anonymous labels, which are already unique
+fn generated(name: &str) -> bool {
+    name.contains('@')
+}
@@ -0,0 +1,699 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    exception::{Exception, Position, Result, SyntaxError},
+    lexer::LexerOptions,
+    listing::{rows, Row},
+    options::{Options, Target},
+    parser::Parser,
+    token::{Command, Comparison, Token, TokenType},
+};
+
+/// Bytes on each line of data
+const DATA_BYTES: usize = 8;
+
+/// Words Octo reads as something other than a label, and `main`, which Octo
+/// starts from. Labels with these names get a leading `_`.
+const RESERVED: [&str; 38] = [
+    "main",
+    "i",
+    "key",
+    "hex",
+    "bighex",
+    "random",
+    "delay",
+    "buzzer",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "long",
+    "plane",
+    "audio",
+    "pitch",
+    "sprite",
+    "jump",
+    "jump0",
+    "return",
+    "clear",
+    "exit",
+    "lores",
+    "hires",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "native",
+    "if",
+    "then",
+    "else",
+    "begin",
+    "end",
+    "loop",
+    "again",
+    "while",
+];
+
+/// Writes the assembled program as Octo source. Instructions are written from the
+/// bytes they assembled to, so the ROM Octo builds is the same, with the labels
+/// kept wherever an instruction points at one. The program starts at `: main`,
+/// which Octo runs from, and the startup stub and data are written as bytes.
+pub fn export(parser: &Parser, rom: &[u8], load: u16) -> String {
+    let mut octo = String::new();
+    if load != 0x200 {
+        octo.push_str(&format!(":org 0x{:X}\n", load));
+    }
+    octo.push_str(": main\n");
+    let mut next = load as usize;
+    for (address, row) in rows(parser) {
+        if address != next {
+            octo.push_str(&format!(":org 0x{:X}\n", address));
+            next = address;
+        }
+        let offset = address - load as usize;
+        match row {
+            Row::Label(label) => octo.push_str(&format!(": {}\n", name(label))),
+            Row::Stub(size) => {
+                octo.push_str(&data(&rom[offset..offset + size]));
+                next += size;
+            }
+            Row::Instruction(cmd, args, _) => {
+                let size = cmd.size(args);
+                let bytes = &rom[offset..offset + size];
+                match instruction(cmd, args, bytes) {
+                    Some(text) => octo.push_str(&format!("\t{}\n", text)),
+                    None => octo.push_str(&data(bytes)),
+                }
+                next += size;
+            }
+        }
+    }
+    octo
+}
+
+/// Octo's options for running the program on the target, with the address of every label
+pub fn options(parser: &Parser, options: &Options) -> String {
+    // The quirks of Octo's presets for each target
+    let (quirks, max_size) = match options.target {
+        Target::Chip8 => ([false, false, true, false, true, true], 3232),
+        Target::SChip => ([true, true, true, true, false, false], 3583),
+        Target::XOChip => ([false, false, false, false, false, false], 65024),
+    };
+    let names = [
+        "shiftQuirks",
+        "loadStoreQuirks",
+        "clipQuirks",
+        "jumpQuirks",
+        "vBlankQuirks",
+        "logicQuirks",
+    ];
+    let mut labels = parser
+        .symbols()
+        .iter()
+        .map(|(label, address)| (name(label), *address))
+        .collect::<Vec<_>>();
+    labels.sort();
+
+    let mut json = String::from("{\n");
+    json.push_str("  \"tickrate\": 20,\n");
+    json.push_str(&format!("  \"maxSize\": {},\n", max_size));
+    for (name, quirk) in names.iter().zip(quirks) {
+        json.push_str(&format!("  \"{}\": {},\n", name, quirk));
+    }
+    json.push_str("  \"vfOrderQuirks\": false,\n");
+    json.push_str("  \"screenRotation\": 0,\n");
+    json.push_str("  \"fontStyle\": \"octo\",\n");
+    json.push_str("  \"touchInputMode\": \"none\",\n");
+    json.push_str("  \"labels\": {");
+    json.push_str(
+        &labels
+            .iter()
+            .map(|(label, address)| format!("\n    {:?}: {}", label, address))
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    json.push_str(if labels.is_empty() { "}\n" } else { "\n  }\n" });
+    json.push_str("}\n");
+    json
+}
+
+/// The Octo statement for an instruction, or `None` when it is written as bytes
+fn instruction(cmd: &Command, args: &[TokenType], bytes: &[u8]) -> Option<String> {
+    let op = u16::from_be_bytes([bytes[0], *bytes.get(1)?]);
+    let (x, y) = ((op >> 8) & 0xF, (op >> 4) & 0xF);
+    let (n, nn, nnn) = (op & 0xF, op & 0xFF, op & 0xFFF);
+    // The label the instruction points at, or the address
+    let target = |address: u16| match args {
+        [TokenType::Label(label)] => name(label),
+        _ => format!("0x{:X}", address),
+    };
+    Some(match cmd {
+        Command::CLR => String::from("clear"),
+        Command::RET => String::from("return"),
+        Command::JMP => format!("jump {}", target(nnn)),
+        Command::CALL => format!(":call {}", target(nnn)),
+        Command::OFFJMP => format!("jump0 {}", target(nnn)),
+        Command::POINT => format!("i := {}", target(nnn)),
+        Command::LONGPOINT => {
+            let address = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+            format!("i := long {}", target(address))
+        }
+        // Octo's conditions say when the next instruction runs, chasm's when it is skipped
+        Command::JMPEQ if op >> 12 == 0x3 => format!("if v{:X} != 0x{:X} then", x, nn),
+        Command::JMPEQ => format!("if v{:X} != v{:X} then", x, y),
+        Command::JMPNE if op >> 12 == 0x4 => format!("if v{:X} == 0x{:X} then", x, nn),
+        Command::JMPNE => format!("if v{:X} == v{:X} then", x, y),
+        Command::JMPEQKEY => format!("if v{:X} -key then", x),
+        Command::JMPNEKEY => format!("if v{:X} key then", x),
+        Command::SET if op >> 12 == 0x6 => format!("v{:X} := 0x{:X}", x, nn),
+        Command::SET => format!("v{:X} := v{:X}", x, y),
+        Command::ADD if op >> 12 == 0x7 => format!("v{:X} += 0x{:X}", x, nn),
+        Command::ADD => format!("v{:X} += v{:X}", x, y),
+        Command::OR => format!("v{:X} |= v{:X}", x, y),
+        Command::AND => format!("v{:X} &= v{:X}", x, y),
+        Command::XOR => format!("v{:X} ^= v{:X}", x, y),
+        Command::SUB => format!("v{:X} -= v{:X}", x, y),
+        Command::SUBFROM => format!("v{:X} =- v{:X}", x, y),
+        Command::SHR => format!("v{:X} >>= v{:X}", x, y),
+        Command::SHL => format!("v{:X} <<= v{:X}", x, y),
+        Command::RAND => format!("v{:X} := random 0x{:X}", x, nn),
+        Command::DRAW => format!("sprite v{:X} v{:X} 0x{:X}", x, y, n),
+        Command::GETDELAY => format!("v{:X} := delay", x),
+        Command::GETKEY => format!("v{:X} := key", x),
+        Command::SETDELAY => format!("delay := v{:X}", x),
+        Command::SETSOUND => format!("buzzer := v{:X}", x),
+        Command::ADDPTR => format!("i += v{:X}", x),
+        Command::SETPTRCHR => format!("i := hex v{:X}", x),
+        Command::SETPTRBIGCHR => format!("i := bighex v{:X}", x),
+        Command::SETPTRDEC => format!("bcd v{:X}", x),
+        Command::DUMP => format!("save v{:X}", x),
+        Command::LOAD => format!("load v{:X}", x),
+        Command::DUMPRANGE => format!("save v{:X} - v{:X}", x, y),
+        Command::LOADRANGE => format!("load v{:X} - v{:X}", x, y),
+        Command::SAVEFLAGS => format!("saveflags v{:X}", x),
+        Command::LOADFLAGS => format!("loadflags v{:X}", x),
+        Command::SCROLLDOWN => format!("scroll-down 0x{:X}", n),
+        Command::SCROLLRIGHT => String::from("scroll-right"),
+        Command::SCROLLLEFT => String::from("scroll-left"),
+        Command::EXIT => String::from("exit"),
+        Command::LORES => String::from("lores"),
+        Command::HIRES => String::from("hires"),
+        Command::PLANE => format!("plane 0x{:X}", x),
+        Command::AUDIO => String::from("audio"),
+        Command::PITCH => format!("pitch := v{:X}", x),
+        _ => return None,
+    })
+}
+
+/// Bytes Octo places as they are, a line of them at a time
+fn data(bytes: &[u8]) -> String {
+    bytes
+        .chunks(DATA_BYTES)
+        .map(|chunk| {
+            let bytes = chunk
+                .iter()
+                .map(|b| format!("0x{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("\t{}\n", bytes)
+        })
+        .collect()
+}
+
+/// The label as Octo can read it
+fn name(label: &str) -> String {
+    let reserved = RESERVED.contains(&label)
+        || label.parse::<i64>().is_ok()
+        || label.starts_with("0x")
+        || label.starts_with("0b");
+    if reserved {
+        format!("_{}", label)
+    } else {
+        label.to_string()
+    }
+}
+
+/// A word of Octo source, which is read a word at a time rather than by line
+struct Word {
+    text: String,
+    position: Position,
+}
+
+struct Import {
+    words: Vec<Word>,
+    at: usize,
+    output: Vec<Token>,
+    /// Values made with `:const`
+    consts: HashMap<String, u16>,
+    /// Registers named with `:alias`
+    aliases: HashMap<String, u8>,
+    case_sensitive: bool,
+}
+
+/// Reads an Octo file as the chasm statements it stands for, a line each, so the
+/// rest of the assembler treats it like any other file. Covers labels, `:const`,
+/// `:alias`, `:call`, `:org`, `:byte`, the instructions, bytes, `if ... then`,
+/// `if ... begin ... else ... end` and `loop ... again`.
+pub fn import(input: &str, filename: Rc<String>, options: &LexerOptions) -> Result<Vec<Token>> {
+    let mut words = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+            let mut text = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|(_, c)| !c.is_whitespace()) {
+                text.push(c);
+                chars.next();
+            }
+            let column = (start + 1, start + 1 + text.len());
+            words.push(Word {
+                text,
+                position: Position::new(i + 1, column, Rc::clone(&filename)),
+            });
+        }
+    }
+    let end = Position::new(input.lines().count() + 1, (1, 1), filename);
+
+    let mut import = Import {
+        words,
+        at: 0,
+        output: Vec::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        case_sensitive: options.case_sensitive,
+    };
+    while import.at < import.words.len() {
+        let position = import.words[import.at].position.clone();
+        let statement = import.statement()?;
+        if !statement.is_empty() {
+            import.emit(statement, &position);
+        }
+    }
+    import.output.push(Token {
+        token: TokenType::Eof,
+        position: end,
+    });
+    Ok(import.output)
+}
+
+impl Import {
+    fn emit(&mut self, tokens: Vec<TokenType>, position: &Position) {
+        for token in tokens.into_iter().chain([TokenType::Eol]) {
+            self.output.push(Token {
+                token,
+                position: position.clone(),
+            });
+        }
+    }
+
+    /// Moves past the next word, returning where it is
+    fn next(&mut self, expected: &str) -> Result<usize> {
+        match self.words.get(self.at) {
+            Some(_) => {
+                self.at += 1;
+                Ok(self.at - 1)
+            }
+            None => {
+                let position = self.words.last().unwrap().position.clone();
+                Err(Box::new(SyntaxError(
+                    format!("Expected {} at the end of the file", expected),
+                    position,
+                )))
+            }
+        }
+    }
+
+    fn text(&mut self, expected: &str) -> Result<String> {
+        let i = self.next(expected)?;
+        Ok(self.words[i].text.clone())
+    }
+
+    /// Reads the next word, which must be `text`
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let i = self.next(&format!("'{}'", text))?;
+        let word = &self.words[i];
+        if word.text != text {
+            return Err(unexpected(word, &format!("'{}'", text)));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let i = self.next("a register")?;
+        let word = &self.words[i];
+        if let Some(&register) = self.aliases.get(&word.text) {
+            return Ok(register);
+        }
+        match word.text.strip_prefix(['v', 'V']) {
+            Some(digit) if digit.len() == 1 => {
+                u8::from_str_radix(digit, 16).map_err(|_| unexpected(word, "a register"))
+            }
+            _ => Err(unexpected(word, "a register")),
+        }
+    }
+
+    /// A number or a `:const`, as the byte or word chasm stores it
+    fn value(&self, word: &Word) -> Option<TokenType> {
+        if let Some(&value) = self.consts.get(&word.text) {
+            return Some(TokenType::Number(value));
+        }
+        let text = word.text.as_str();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let value = if let Some(hex) = text.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = text.strip_prefix("0b") {
+            u16::from_str_radix(binary, 2).ok()?
+        } else {
+            text.parse().ok()?
+        };
+        Some(if negative {
+            TokenType::Negative(value)
+        } else {
+            TokenType::Number(value)
+        })
+    }
+
+    fn number(&mut self) -> Result<TokenType> {
+        let i = self.next("a number")?;
+        let word = &self.words[i];
+        self.value(word).ok_or_else(|| unexpected(word, "a number"))
+    }
+
+    /// A number, or a label as chasm spells it
+    fn address(&mut self) -> Result<TokenType> {
+        let i = self.next("an address or a label")?;
+        let word = &self.words[i];
+        Ok(match self.value(word) {
+            Some(value) => value,
+            None => TokenType::Label(self.label(&word.text)),
+        })
+    }
+
+    fn label(&self, name: &str) -> String {
+        if self.case_sensitive {
+            name.to_string()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    /// A statement that has to be an instruction, such as the one after `then`
+    fn instruction(&mut self, after: &str) -> Result<Vec<TokenType>> {
+        let start = self.at;
+        let statement = self.statement()?;
+        match statement.first() {
+            Some(TokenType::Command(_)) => Ok(statement),
+            _ => Err(unexpected(
+                &self.words[start],
+                &format!("an instruction after '{}'", after),
+            )),
+        }
+    }
+
+    /// Reads one statement as the tokens of a chasm line, nothing for `:const` and `:alias`
+    fn statement(&mut self) -> Result<Vec<TokenType>> {
+        let index = self.at;
+        let text = self.text("a statement")?;
+        let register = |text: &str, aliases: &HashMap<String, u8>| {
+            aliases.contains_key(text)
+                || text.len() == 2
+                    && text.starts_with(['v', 'V'])
+                    && text.chars().nth(1).is_some_and(|c| c.is_ascii_hexdigit())
+        };
+        Ok(match text.as_str() {
+            ":" => {
+                let name = self.text("a label")?;
+                vec![TokenType::Label(self.label(&name)), TokenType::Colon]
+            }
+            ":const" => {
+                let name = self.text("a name")?;
+                let TokenType::Number(value) = self.number()? else {
+                    return Err(unexpected(&self.words[self.at - 1], "a positive number"));
+                };
+                self.consts.insert(name, value);
+                vec![]
+            }
+            ":alias" => {
+                let name = self.text("a name")?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                vec![]
+            }
+            ":call" => vec![command(Command::CALL), self.address()?],
+            ":org" => vec![command(Command::ORG), self.number()?],
+            ":byte" => vec![command(Command::BYTE), self.number()?],
+            "clear" => vec![command(Command::CLR)],
+            "return" | ";" => vec![command(Command::RET)],
+            "exit" => vec![command(Command::EXIT)],
+            "lores" => vec![command(Command::LORES)],
+            "hires" => vec![command(Command::HIRES)],
+            "scroll-left" => vec![command(Command::SCROLLLEFT)],
+            "scroll-right" => vec![command(Command::SCROLLRIGHT)],
+            "scroll-down" => vec![command(Command::SCROLLDOWN), self.number()?],
+            "audio" => vec![command(Command::AUDIO)],
+            "plane" => vec![command(Command::PLANE), self.number()?],
+            "jump" => vec![command(Command::JMP), self.address()?],
+            "jump0" => vec![command(Command::OFFJMP), self.address()?],
+            "bcd" => vec![command(Command::SETPTRDEC), reg(self.register()?)],
+            "saveflags" => vec![command(Command::SAVEFLAGS), reg(self.register()?)],
+            "loadflags" => vec![command(Command::LOADFLAGS), reg(self.register()?)],
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.words.get(self.at).is_some_and(|word| word.text == "-") {
+                    self.at += 1;
+                    let y = self.register()?;
+                    let cmd = match text.as_str() {
+                        "save" => Command::DUMPRANGE,
+                        _ => Command::LOADRANGE,
+                    };
+                    vec![command(cmd), reg(x), reg(y)]
+                } else {
+                    let cmd = match text.as_str() {
+                        "save" => Command::DUMP,
+                        _ => Command::LOAD,
+                    };
+                    vec![command(cmd), reg(x)]
+                }
+            }
+            "sprite" => vec![
+                command(Command::DRAW),
+                reg(self.register()?),
+                reg(self.register()?),
+                self.number()?,
+            ],
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let cmd = match text.as_str() {
+                    "delay" => Command::SETDELAY,
+                    "buzzer" => Command::SETSOUND,
+                    _ => Command::PITCH,
+                };
+                vec![command(cmd), reg(self.register()?)]
+            }
+            "i" => {
+                let operator = self.text("':=' or '+='")?;
+                match operator.as_str() {
+                    "+=" => vec![command(Command::ADDPTR), reg(self.register()?)],
+                    ":=" => match self.words.get(self.at).map(|word| word.text.as_str()) {
+                        Some("long") => {
+                            self.at += 1;
+                            vec![command(Command::LONGPOINT), self.address()?]
+                        }
+                        Some("hex") => {
+                            self.at += 1;
+                            vec![command(Command::SETPTRCHR), reg(self.register()?)]
+                        }
+                        Some("bighex") => {
+                            self.at += 1;
+                            vec![command(Command::SETPTRBIGCHR), reg(self.register()?)]
+                        }
+                        _ => vec![command(Command::POINT), self.address()?],
+                    },
+                    _ => return Err(unexpected(&self.words[self.at - 1], "':=' or '+='")),
+                }
+            }
+            "if" => {
+                let x = self.register()?;
+                let operator = self.text("a comparison")?;
+                // Octo runs the next instruction when the condition holds, so the
+                // skip is the opposite one
+                let (skip, condition) = match operator.as_str() {
+                    "key" => (Some(vec![command(Command::JMPNEKEY), reg(x)]), None),
+                    "-key" => (Some(vec![command(Command::JMPEQKEY), reg(x)]), None),
+                    _ => {
+                        let comparison = match operator.as_str() {
+                            "==" => Comparison::Eq,
+                            "!=" => Comparison::Ne,
+                            "<" => Comparison::Lt,
+                            ">=" => Comparison::Ge,
+                            ">" => Comparison::Gt,
+                            "<=" => Comparison::Le,
+                            _ => {
+                                return Err(unexpected(
+                                    &self.words[self.at - 1],
+                                    "==, !=, <, >=, >, <=, key or -key",
+                                ))
+                            }
+                        };
+                        let operand = match self.words.get(self.at) {
+                            Some(word) if register(&word.text, &self.aliases) => {
+                                reg(self.register()?)
+                            }
+                            _ => self.number()?,
+                        };
+                        let skip = match comparison {
+                            Comparison::Eq => Some(Command::JMPNE),
+                            Comparison::Ne => Some(Command::JMPEQ),
+                            _ => None,
+                        }
+                        .map(|cmd| vec![command(cmd), reg(x), operand.clone()]);
+                        let condition = vec![reg(x), TokenType::Comparison(comparison), operand];
+                        (skip, Some(condition))
+                    }
+                };
+                let i = self.next("'then' or 'begin'")?;
+                let keyword = &self.words[i];
+                let if_then = |condition: Vec<TokenType>| {
+                    std::iter::once(TokenType::Label(String::from("if")))
+                        .chain(condition)
+                        .chain([TokenType::Label(String::from("then"))])
+                };
+                match (keyword.text.as_str(), skip, condition) {
+                    // A single skip, like Octo's, so conditions can be chained
+                    ("then", Some(skip), _) => skip,
+                    ("then", None, Some(condition)) => {
+                        let then = self.instruction("then")?;
+                        if_then(condition).chain(then).collect()
+                    }
+                    ("begin", _, Some(condition)) => if_then(condition).collect(),
+                    ("begin", _, None) => {
+                        return Err(Box::new(SyntaxError(
+                            String::from("Key conditions only work with 'then', not 'begin'"),
+                            keyword.position.clone(),
+                        )))
+                    }
+                    _ => return Err(unexpected(keyword, "'then' or 'begin'")),
+                }
+            }
+            "else" | "end" | "loop" | "again" => vec![TokenType::Label(text)],
+            _ if register(&text, &self.aliases) => {
+                self.at = index;
+                let x = self.register()?;
+                let operator = self.text("an assignment")?;
+                let operand = self.words.get(self.at).map(|word| word.text.clone());
+                match (operator.as_str(), operand.as_deref()) {
+                    (":=", Some("random")) => {
+                        self.at += 1;
+                        vec![command(Command::RAND), reg(x), self.number()?]
+                    }
+                    (":=", Some("key")) => {
+                        self.at += 1;
+                        vec![command(Command::GETKEY), reg(x)]
+                    }
+                    (":=", Some("delay")) => {
+                        self.at += 1;
+                        vec![command(Command::GETDELAY), reg(x)]
+                    }
+                    (":=" | "+=" | "-=", Some(operand)) if !register(operand, &self.aliases) => {
+                        let cmd = match operator.as_str() {
+                            ":=" => Command::SET,
+                            _ => Command::ADD,
+                        };
+                        let value = match (operator.as_str(), self.number()?) {
+                            // There's no subtracting a byte, so add its negative
+                            ("-=", TokenType::Number(n)) => TokenType::Negative(n),
+                            ("-=", TokenType::Negative(n)) => TokenType::Number(n),
+                            (_, value) => value,
+                        };
+                        vec![command(cmd), reg(x), value]
+                    }
+                    (">>=" | "<<=", _) => {
+                        let y = self.register()?;
+                        let (cmd, low) = match operator.as_str() {
+                            ">>=" => (Command::SHR, 0x6),
+                            _ => (Command::SHL, 0xE),
+                        };
+                        match y {
+                            // Only the shifts of vX by itself are chasm instructions,
+                            // the rest are placed as the words they are
+                            0 => vec![command(cmd), reg(x)],
+                            _ => vec![
+                                command(Command::CHIP),
+                                TokenType::Number(0x8000 | (x as u16) << 8 | (y as u16) << 4 | low),
+                            ],
+                        }
+                    }
+                    _ => {
+                        let cmd = match operator.as_str() {
+                            ":=" => Command::SET,
+                            "+=" => Command::ADD,
+                            "-=" => Command::SUB,
+                            "=-" => Command::SUBFROM,
+                            "|=" => Command::OR,
+                            "&=" => Command::AND,
+                            "^=" => Command::XOR,
+                            _ => {
+                                return Err(unexpected(
+                                    &self.words[self.at - 1],
+                                    "an assignment such as := or +=",
+                                ))
+                            }
+                        };
+                        vec![command(cmd), reg(x), reg(self.register()?)]
+                    }
+                }
+            }
+            _ => {
+                let word = &self.words[index];
+                if let Some(value) = self.value(word) {
+                    // Bytes on the same line are placed together
+                    let line = word.position.line;
+                    let mut bytes = vec![command(Command::BYTE), value];
+                    while let Some(value) = self
+                        .words
+                        .get(self.at)
+                        .filter(|word| word.position.line == line)
+                        .and_then(|word| self.value(word))
+                    {
+                        bytes.push(value);
+                        self.at += 1;
+                    }
+                    bytes
+                } else if text.starts_with(':') || RESERVED.contains(&text.as_str()) {
+                    return Err(Box::new(SyntaxError(
+                        format!("'{}' isn't part of the Octo that chasm reads", text),
+                        word.position.clone(),
+                    )));
+                } else {
+                    // A bare name calls that label
+                    vec![command(Command::CALL), TokenType::Label(self.label(&text))]
+                }
+            }
+        })
+    }
+}
+
+fn command(command: Command) -> TokenType {
+    TokenType::Command(command)
+}
+
+fn reg(register: u8) -> TokenType {
+    TokenType::Register(register)
+}
+
+fn unexpected(word: &Word, expected: &str) -> Box<dyn Exception> {
+    Box::new(SyntaxError(
+        format!("Expected {}, found '{}'", expected, word.text),
+        word.position.clone(),
+    ))
+}
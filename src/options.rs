@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use crate::{
+    exception::{InvalidArgument, Result},
+    expr::parse_number,
+    lexer::LexerOptions,
+};
+
+/// Where programs are loaded on the original interpreter
+pub const STANDARD_LOAD: u16 = 0x200;
+/// Where programs are loaded on the ETI-660
+pub const ETI660_LOAD: u16 = 0x600;
+
+/// Instruction sets, each one extending the one before it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Target {
+    Chip8,
+    SChip,
+    XOChip,
+}
+
+impl Target {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Chip8 => "CHIP-8",
+            Target::SChip => "SUPER-CHIP",
+            Target::XOChip => "XO-CHIP",
+        }
+    }
+
+    /// Bytes of memory the interpreter has
+    pub fn memory(&self) -> usize {
+        match self {
+            Target::Chip8 | Target::SChip => 0x1000,
+            Target::XOChip => 0x10000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Halt {
+    /// Jump to the same address forever
+    Loop,
+    /// Jump back to the start of the program
+    Restart,
+    /// Quit the interpreter, SUPER-CHIP only
+    Exit,
+}
+
+/// What to do when a skip is followed by a two word instruction
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LongSkip {
+    /// Report it as an error
+    Error,
+    /// Invert the skip and jump over the instruction
+    Rewrite,
+    /// Trust the interpreter to skip both words, as XO-CHIP interpreters do
+    Native,
+}
+
+/// How the assembled ROM is written out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// The bytes as they are loaded
+    Raw,
+    /// Intel HEX records, for flashing tools
+    IntelHex,
+    /// Lines of hex bytes after their address
+    HexDump,
+    /// A Rust `const` byte array
+    Rust,
+    /// A C byte array and its length
+    C,
+    /// Base64 text, for pasting into web emulators
+    Base64,
+}
+
+impl Format {
+    /// Where the ROM is written when no output is given
+    pub fn default_output(&self) -> &'static str {
+        match self {
+            Format::Raw => "ibm.ch8",
+            Format::IntelHex => "ibm.hex",
+            Format::HexDump => "ibm.txt",
+            Format::Rust => "ibm.rs",
+            Format::C => "ibm.h",
+            Format::Base64 => "ibm.b64",
+        }
+    }
+}
+
+pub struct Options {
+    pub input: String,
+    pub output: Option<String>,
+    pub format: Format,
+    pub listing: Option<String>,
+    pub octo: Option<String>,
+    pub octo_options: Option<String>,
+    pub defines: HashMap<String, String>,
+    pub preprocess_only: bool,
+    pub entry: String,
+    pub startup: Option<String>,
+    pub stub: bool,
+    pub halt: Halt,
+    pub load: u16,
+    pub target: Target,
+    pub long_skip: LongSkip,
+    pub lexer: LexerOptions,
+}
+
+impl Options {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            input: String::from("example.chasm"),
+            output: None,
+            format: Format::Raw,
+            listing: None,
+            octo: None,
+            octo_options: None,
+            defines: HashMap::new(),
+            preprocess_only: false,
+            entry: String::from("main"),
+            startup: None,
+            stub: true,
+            halt: Halt::Loop,
+            load: STANDARD_LOAD,
+            target: Target::Chip8,
+            long_skip: LongSkip::Error,
+            lexer: LexerOptions {
+                radix: 16,
+                warn_bare_numbers: false,
+                case_sensitive: false,
+            },
+        };
+        // Read once the radix is known
+        let mut load = None;
+        // Flags are applied once the target is known, so they can override the predefined ones
+        let mut flags = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-D" | "-U" | "-o" | "--entry" | "--startup" | "--halt" | "--load" | "--target"
+                | "--long-skip" | "--listing" | "--radix" | "--format" | "--octo"
+                | "--octo-options" => {
+                    let value = match args.next() {
+                        Some(value) => value,
+                        None => {
+                            return Err(Box::new(InvalidArgument(format!(
+                                "Expected a value after {}",
+                                arg
+                            ))))
+                        }
+                    };
+                    match arg.as_str() {
+                        "-D" | "-U" => flags.push((arg, value)),
+                        "--listing" => options.listing = Some(value),
+                        "--octo" => options.octo = Some(value),
+                        "--octo-options" => options.octo_options = Some(value),
+                        "--entry" => options.entry = value,
+                        "--startup" => options.startup = Some(value),
+                        "--halt" => {
+                            options.halt = match value.as_str() {
+                                "loop" => Halt::Loop,
+                                "restart" => Halt::Restart,
+                                "exit" => Halt::Exit,
+                                _ => {
+                                    return Err(Box::new(InvalidArgument(format!(
+                                        "Unknown halt '{}', expected loop, restart or exit",
+                                        value
+                                    ))))
+                                }
+                            }
+                        }
+                        "--long-skip" => {
+                            options.long_skip = match value.as_str() {
+                                "error" => LongSkip::Error,
+                                "rewrite" => LongSkip::Rewrite,
+                                "native" => LongSkip::Native,
+                                _ => {
+                                    return Err(Box::new(InvalidArgument(format!(
+                                        "Unknown long skip '{}', expected error, rewrite or native",
+                                        value
+                                    ))))
+                                }
+                            }
+                        }
+                        "--target" => {
+                            options.target = match value.as_str() {
+                                "chip8" => Target::Chip8,
+                                "schip" => Target::SChip,
+                                "xochip" => Target::XOChip,
+                                _ => {
+                                    return Err(Box::new(InvalidArgument(format!(
+                                        "Unknown target '{}', expected chip8, schip or xochip",
+                                        value
+                                    ))))
+                                }
+                            }
+                        }
+                        "--format" => {
+                            options.format = match value.as_str() {
+                                "raw" => Format::Raw,
+                                "ihex" => Format::IntelHex,
+                                "hex" => Format::HexDump,
+                                "rust" => Format::Rust,
+                                "c" => Format::C,
+                                "base64" => Format::Base64,
+                                _ => {
+                                    return Err(Box::new(InvalidArgument(format!(
+                                        "Unknown format '{}', use raw, ihex, hex, rust, c, base64",
+                                        value
+                                    ))))
+                                }
+                            }
+                        }
+                        "--load" => load = Some(value),
+                        "--radix" => {
+                            options.lexer.radix = match value.as_str() {
+                                "2" => 2,
+                                "8" => 8,
+                                "10" => 10,
+                                "16" => 16,
+                                _ => {
+                                    return Err(Box::new(InvalidArgument(format!(
+                                        "Invalid radix '{}', expected 2, 8, 10 or 16",
+                                        value
+                                    ))))
+                                }
+                            }
+                        }
+                        _ => options.output = Some(value),
+                    }
+                }
+                "-E" => options.preprocess_only = true,
+                "--no-stub" => options.stub = false,
+                "--warn-bare-numbers" => options.lexer.warn_bare_numbers = true,
+                "--case-sensitive" => options.lexer.case_sensitive = true,
+                _ if arg.starts_with("-D") || arg.starts_with("-U") => {
+                    flags.push((arg[..2].to_string(), arg[2..].to_string()))
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(Box::new(InvalidArgument(format!(
+                        "Unknown option '{}'",
+                        arg
+                    ))))
+                }
+                _ => options.input = arg,
+            }
+        }
+        if let Some(value) = load {
+            options.load = match value.as_str() {
+                "standard" => STANDARD_LOAD,
+                "eti" | "eti660" => ETI660_LOAD,
+                _ => match parse_number(&value, options.lexer.radix) {
+                    // Below 0x200 is the interpreter's and the font's, and odd addresses
+                    // would put every instruction off its word
+                    Some(load @ 0x200..=0xFFF) if load % 2 == 0 => load as u16,
+                    _ => {
+                        return Err(Box::new(InvalidArgument(format!(
+                            "Invalid load address '{}', expected standard, eti or an even address from 0x200 to 0xFFE",
+                            value
+                        ))))
+                    }
+                },
+            }
+        }
+        // Labels are lowercase unless they keep their case
+        if !options.lexer.case_sensitive {
+            options.entry.make_ascii_lowercase();
+            if let Some(startup) = &mut options.startup {
+                startup.make_ascii_lowercase();
+            }
+        }
+        if options.halt == Halt::Exit && options.target < Target::SChip {
+            return Err(Box::new(InvalidArgument(String::from(
+                "--halt exit needs the SUPER-CHIP EXIT instruction, use --target schip",
+            ))));
+        }
+        options.defines = options.predefined();
+        for (flag, value) in flags {
+            match flag.as_str() {
+                "-D" => options.define(&value)?,
+                _ => options.undefine(&value)?,
+            }
+        }
+        Ok(options)
+    }
+
+    /// Symbols describing the target, visible to every conditional directive
+    fn predefined(&self) -> HashMap<String, String> {
+        let mut defines = HashMap::from([
+            (String::from("__CHIP8__"), String::from("1")),
+            (String::from("__START__"), format!("0x{:X}", self.load)),
+        ]);
+        if self.target >= Target::SChip {
+            defines.insert(String::from("__SCHIP__"), String::from("1"));
+        }
+        if self.target >= Target::XOChip {
+            defines.insert(String::from("__XOCHIP__"), String::from("1"));
+        }
+        defines
+    }
+
+    fn define(&mut self, arg: &str) -> Result<()> {
+        let (name, value) = split_define(arg);
+        // A name starting with '-' is the next option, as in `-D -o out.ch8`
+        if name.is_empty() || name.starts_with('-') {
+            return Err(Box::new(InvalidArgument(format!(
+                "Invalid define '{}', expected NAME or NAME=VALUE",
+                arg
+            ))));
+        }
+        self.defines.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn undefine(&mut self, arg: &str) -> Result<()> {
+        if arg.is_empty() {
+            return Err(Box::new(InvalidArgument(String::from(
+                "Expected a flag name after -U",
+            ))));
+        }
+        if arg.starts_with('-') {
+            return Err(Box::new(InvalidArgument(format!(
+                "Expected a flag name after -U, found the option '{}'",
+                arg
+            ))));
+        }
+        self.defines.remove(arg);
+        Ok(())
+    }
+}
+
+/// Splits `NAME=VALUE` into its parts, a bare `NAME` is defined as `1`
+pub fn split_define(arg: &str) -> (&str, &str) {
+    match arg.split_once('=') {
+        Some((name, value)) => (name.trim(), value.trim()),
+        None => (arg.trim(), "1"),
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use crate::options::Format;
+
+/// Bytes per Intel HEX record and per hex dump line
+const LINE_BYTES: usize = 16;
+/// Bytes per line of a Rust or C array
+const ARRAY_BYTES: usize = 12;
+
+/// C and Rust keywords, which can't name the array
+const KEYWORDS: &str = "abstract alignas alignof as async auto await become bool box break case \
+    char const constexpr continue crate default do double dyn else enum extern false final float \
+    fn for gen goto if impl in inline int let long loop macro match mod move mut nullptr override \
+    priv pub ref register restrict return self short signed sizeof static static_assert struct \
+    super switch thread_local trait true try type typedef typeof typeof_unqual union unsafe \
+    unsigned unsized use virtual void volatile where while yield";
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Writes the ROM out in `format`. Addresses start at `load`, and arrays are named
+/// after the file they are written to.
+pub fn output(rom: &[u8], load: u16, format: Format, path: &str) -> Vec<u8> {
+    match format {
+        Format::Raw => rom.to_vec(),
+        Format::IntelHex => intel_hex(rom, load),
+        Format::HexDump => hex_dump(rom, load),
+        Format::Rust => {
+            let name = identifier(path).to_ascii_uppercase();
+            format!(
+                "pub const {}: [u8; {}] = [\n{}];\n",
+                name,
+                rom.len(),
+                array(rom)
+            )
+        }
+        .into_bytes(),
+        Format::C => {
+            let name = identifier(path).to_ascii_lowercase();
+            format!(
+                "const unsigned char {}[{}] = {{\n{}}};\nconst unsigned int {}_len = {};\n",
+                name,
+                rom.len(),
+                array(rom),
+                name,
+                rom.len()
+            )
+        }
+        .into_bytes(),
+        Format::Base64 => {
+            let mut text = base64(rom);
+            text.push('\n');
+            text.into_bytes()
+        }
+    }
+}
+
+/// Data records of up to 16 bytes, then the end of file record
+fn intel_hex(rom: &[u8], load: u16) -> Vec<u8> {
+    let mut text = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let [high, low] = address.to_be_bytes();
+        let sum = [data.len() as u8, high, low, kind]
+            .iter()
+            .chain(data)
+            .fold(0u8, |sum, &b| sum.wrapping_add(b));
+        text.push_str(&format!(":{:02X}{:04X}{:02X}", data.len(), address, kind));
+        data.iter()
+            .for_each(|b| text.push_str(&format!("{:02X}", b)));
+        text.push_str(&format!("{:02X}\n", sum.wrapping_neg()));
+    };
+    for (i, chunk) in rom.chunks(LINE_BYTES).enumerate() {
+        record(0, load + (i * LINE_BYTES) as u16, chunk);
+    }
+    record(1, 0, &[]);
+    text.into_bytes()
+}
+
+/// 16 bytes a line, each line starting with its address
+fn hex_dump(rom: &[u8], load: u16) -> Vec<u8> {
+    rom.chunks(LINE_BYTES)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let bytes = chunk
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{:04X}: {}\n", load as usize + i * LINE_BYTES, bytes)
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// The body of a Rust or C array, indented a line at a time
+fn array(rom: &[u8]) -> String {
+    rom.chunks(ARRAY_BYTES)
+        .map(|chunk| {
+            let bytes = chunk
+                .iter()
+                .map(|b| format!("0x{:02X},", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("    {}\n", bytes)
+        })
+        .collect()
+}
+
+fn base64(rom: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in rom.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// The name of the file without its extension, usable as a Rust or C identifier.
+/// Names that start with a digit or are keywords get a `rom_` prefix.
+fn identifier(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("rom");
+    let name = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    match name.chars().next() {
+        Some(c)
+            if !c.is_ascii_digit()
+                && !KEYWORDS
+                    .split_whitespace()
+                    .any(|keyword| keyword.eq_ignore_ascii_case(&name)) =>
+        {
+            name
+        }
+        _ => format!("rom_{}", name),
+    }
+}
@@ -1,14 +1,23 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    fs,
+    collections::{hash_map::Entry, HashMap},
+    fs, iter, mem,
     rc::Rc,
     vec,
 };
 
 use crate::{
-    exception::{FileException, NoMain, Result, SyntaxError, Undefined},
+    exception::{
+        AssertionFailed, Exception, FileException, Info, MemoryOverlap, MprocessorException,
+        NoMain, NumberOverflow, Position, ProgramTooLarge, Redefinition, Result, SkipHazard,
+        SyntaxError, Undefined, Warning,
+    },
+    expr::{evaluate, parse_number},
     intruction::InstructionArg,
-    lexer::lex,
+    lexer::{lex, LexerOptions, Spellings},
+    lower::lower,
+    namespace::Namespaces,
+    options::{split_define, Halt, LongSkip, Options, Target},
+    sprite::sprites,
     token::{Command, MprocessorDirective, Token, TokenType},
 };
 
@@ -16,27 +25,71 @@ pub struct Parser {
     tokens: Vec<Token>,
     current_token: usize,
     ifs: Vec<bool>,
-    defined: HashSet<String>,
+    defined: HashMap<String, String>,
     labels: HashMap<String, usize>,
-    instructions: Vec<(Command, Vec<TokenType>)>,
-    instructions_len: usize,
-    has_main: bool,
+    instructions: Vec<(usize, Command, Vec<TokenType>, Position)>,
+    start: usize,
+    address: usize,
+    regions: Vec<(usize, usize, Option<Position>)>,
+    entry: String,
+    startup: Option<String>,
+    stub: bool,
+    halt: Halt,
+    target: Target,
+    long_skip: LongSkip,
+    asserts: Vec<(String, Position, HashMap<String, String>, u32)>,
+    lexer: LexerOptions,
+    /// The radix each file has switched to with %radix
+    radixes: HashMap<String, u32>,
+    namespaces: Namespaces,
+    /// How labels were spelled before they were lowercased, for messages
+    spellings: Spellings,
+    /// Only preprocessing for -E, whose output has no includes to keep namespaces on
+    preprocess_only: bool,
+    diagnostics: Vec<Box<dyn Exception>>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<Token>, spellings: Spellings, options: &Options) -> Self {
+        let stub_len = match (options.stub, &options.startup) {
+            (false, _) => 0,
+            (true, None) => 4,
+            (true, Some(_)) => 6,
+        };
         Self {
             tokens,
             current_token: 0,
             ifs: Vec::new(),
-            defined: HashSet::new(),
+            defined: options.defines.clone(),
             labels: HashMap::new(),
             instructions: Vec::new(),
-            instructions_len: 0,
-            has_main: false,
+            start: options.load as usize,
+            address: options.load as usize + stub_len,
+            regions: vec![(
+                options.load as usize,
+                options.load as usize + stub_len,
+                None,
+            )],
+            entry: options.entry.clone(),
+            startup: options.startup.clone(),
+            stub: options.stub,
+            halt: options.halt,
+            target: options.target,
+            long_skip: options.long_skip,
+            asserts: Vec::new(),
+            lexer: options.lexer.clone(),
+            radixes: HashMap::new(),
+            namespaces: Namespaces::default(),
+            spellings,
+            preprocess_only: options.preprocess_only,
+            diagnostics: Vec::new(),
         }
     }
 
+    pub fn diagnostics(&self) -> &[Box<dyn Exception>] {
+        &self.diagnostics
+    }
+
     fn advance(&mut self) {
         self.current_token += 1;
     }
@@ -53,35 +106,166 @@ impl Parser {
         while self.current_token().token != TokenType::Eof {
             func(self)?;
         }
+        if self.stub {
+            for (label, purpose) in iter::once((&self.entry, "to start execution at"))
+                .chain(self.startup.iter().map(|s| (s, "for the startup routine")))
+            {
+                if !self.labels.contains_key(label) {
+                    return Err(Box::new(NoMain(
+                        format!("No '{}' label was found {}", label, purpose),
+                        self.current_token().position.file.clone(),
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn symbols(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+
+    /// Every instruction after lowering, in source order
+    pub fn instructions(&self) -> &[(usize, Command, Vec<TokenType>, Position)] {
+        &self.instructions
+    }
+
+    /// Where the startup stub is, if there is one
+    pub fn stub_region(&self) -> Option<(usize, usize)> {
+        self.regions
+            .iter()
+            .find(|(_, _, position)| position.is_none())
+            .map(|&(start, end, _)| (start, end))
+            .filter(|(start, end)| start < end)
+    }
+
+    /// Resolves includes, flags and conditionals, leaving only the tokens that get assembled
+    pub fn preprocess(&mut self) -> Result<()> {
+        let mut processed = Vec::new();
+        while self.current_token().token != TokenType::Eof {
+            if let TokenType::MprocessorDirective(_) = self.current_token().token {
+                self.m_process()?;
+            } else {
+                if !self.ignore() {
+                    processed.push(self.current_token().clone());
+                }
+                self.advance();
+            }
+        }
         if !self.ifs.is_empty() {
             return Err(Box::new(SyntaxError(
                 "%?# or %?! were not ended".to_string(),
                 self.current_token().position.clone(),
             )));
         }
-        if !self.has_main {
-            return Err(Box::new(NoMain(self.current_token().position.file.clone())));
-        }
+        processed.push(self.current_token().clone());
+        self.tokens = processed;
+        self.current_token = 0;
         Ok(())
     }
 
-    pub fn parse(&mut self) -> Result<Vec<(Command, Vec<InstructionArg>)>> {
+    pub fn parse(&mut self) -> Result<Vec<(u16, Command, Vec<InstructionArg>)>> {
+        self.preprocess()?;
+        self.tokens = sprites(mem::take(&mut self.tokens))?;
+        self.tokens = lower(mem::take(&mut self.tokens), &mut self.diagnostics)?;
+        self.tokens = self.namespaces.resolve(mem::take(&mut self.tokens))?;
         self.statements(Self::labels)?;
         self.current_token = 0;
         self.statements(Self::statement)?;
-        Ok(self.convert_instructions())
+        self.check_layout()?;
+        self.check_asserts()?;
+        self.convert_instructions()
     }
 
-    fn convert_instructions(&self) -> Vec<(Command, Vec<InstructionArg>)> {
-        println!("{:?}", self.labels);
-        let mut instructions = vec![
-            (
-                Command::CALL,
-                vec![InstructionArg::Label(self.labels["main"] as u16 + 2)],
-            ),
-            (Command::JMP, vec![InstructionArg::Label(1)]),
-        ];
-        for (cmd, args) in &self.instructions {
+    fn check_asserts(&self) -> Result<()> {
+        for (arg, position, defined, radix) in &self.asserts {
+            let (expr, message) = match arg.find('"') {
+                Some(start) => match arg[..start].trim_end().strip_suffix(',') {
+                    Some(expr) => (expr, arg[start..].trim().trim_matches('"').to_string()),
+                    None => {
+                        return Err(Box::new(SyntaxError(
+                            "Expected ',' between the assertion and its message".to_string(),
+                            position.clone(),
+                        )))
+                    }
+                },
+                None => (arg.as_str(), format!("Assertion '{}' failed", arg)),
+            };
+            let value = evaluate(expr, position, *radix, |symbol| match defined.get(symbol) {
+                Some(value) => parse_number(value, *radix),
+                None => self.address(&self.identifier(symbol)),
+            })?;
+            if value == 0 {
+                return Err(Box::new(AssertionFailed(message, position.clone())));
+            }
+        }
+        Ok(())
+    }
+
+    fn address(&self, label: &str) -> Option<i64> {
+        self.labels.get(label).map(|&l| l as i64)
+    }
+
+    fn check_layout(&mut self) -> Result<()> {
+        self.regions.sort_by_key(|&(start, end, _)| (start, end));
+        // A region that starts early can still end the furthest, as with RESERVE before an ORG
+        let last = self
+            .regions
+            .iter()
+            .filter_map(|(_, end, position)| Some((end, position.as_ref()?)))
+            .max_by_key(|&(end, _)| *end);
+        if let Some((end, position)) = last {
+            let memory = self.target.memory();
+            if *end > memory {
+                return Err(Box::new(ProgramTooLarge(
+                    format!(
+                        "The program ends at 0x{:03X}, past the end of {} memory at 0x{:03X}. Loaded at 0x{:03X} it can be at most {} bytes",
+                        end,
+                        self.target.name(),
+                        memory,
+                        self.start,
+                        memory - self.start
+                    ),
+                    position.clone(),
+                )));
+            }
+        }
+        for pair in self.regions.windows(2) {
+            let ((start, end, ref first), (next, next_end, ref second)) = (&pair[0], &pair[1]);
+            if next < end {
+                let (position, other) = match (first, second) {
+                    (_, Some(position)) => (position, first),
+                    (Some(position), None) => (position, second),
+                    (None, None) => unreachable!(),
+                };
+                let other = match other {
+                    Some(other) => format!("{}", other),
+                    None => "the startup code".to_string(),
+                };
+                return Err(Box::new(MemoryOverlap(
+                    format!(
+                        "0x{:03X}..0x{:03X} overlaps with {} at 0x{:03X}..0x{:03X}",
+                        next, next_end, other, start, end
+                    ),
+                    position.clone(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn convert_instructions(&self) -> Result<Vec<(u16, Command, Vec<InstructionArg>)>> {
+        let mut instructions = self
+            .stub()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (cmd, args))| ((self.start + 2 * i) as u16, cmd, args))
+            .collect::<Vec<_>>();
+        for (address, cmd, args, position) in &self.instructions {
             let mut new_args = Vec::new();
             for arg in args {
                 match arg {
@@ -91,27 +275,77 @@ impl Parser {
                     }
                     TokenType::Number(n) => new_args.push(InstructionArg::Num(*n)),
                     TokenType::Label(l) => {
-                        new_args.push(InstructionArg::Label(self.labels[l] as u16 + 2))
+                        if self.labels[l] > 0xFFF && *cmd != Command::LONGPOINT {
+                            return Err(Box::new(NumberOverflow(
+                                format!(
+                                    "label '{}' is at 0x{:X}, which does not fit in {:?}'s 12 bit address{}",
+                                    l,
+                                    self.labels[l],
+                                    cmd,
+                                    if *cmd == Command::POINT {
+                                        ", LONGPOINT can point anywhere in memory"
+                                    } else {
+                                        ""
+                                    }
+                                ),
+                                position.clone(),
+                            )));
+                        }
+                        new_args.push(InstructionArg::Label(self.labels[l] as u16))
                     }
                     _ => unreachable!(),
                 }
             }
-            instructions.push((cmd.clone(), new_args));
+            instructions.push((*address as u16, cmd.clone(), new_args));
         }
-        instructions
+        Ok(instructions)
+    }
+
+    /// The startup code at the start of the program, which calls the startup routine
+    /// and the entry point, then halts once they return
+    fn stub(&self) -> Vec<(Command, Vec<InstructionArg>)> {
+        if !self.stub {
+            return Vec::new();
+        }
+        let mut stub = Vec::new();
+        for label in self.startup.iter().chain(iter::once(&self.entry)) {
+            stub.push((
+                Command::CALL,
+                vec![InstructionArg::Label(self.labels[label] as u16)],
+            ));
+        }
+        stub.push(match self.halt {
+            Halt::Loop => (
+                Command::JMP,
+                vec![InstructionArg::Label((self.start + 2 * stub.len()) as u16)],
+            ),
+            Halt::Restart => (Command::JMP, vec![InstructionArg::Label(self.start as u16)]),
+            Halt::Exit => (Command::EXIT, vec![]),
+        });
+        stub
     }
 
     fn labels(&mut self) -> Result<()> {
-        let ignore = self.ignore();
         match self.current_token().token {
-            TokenType::Label(_) if !ignore => return self.label(),
-            TokenType::MprocessorDirective(_) => return self.m_process(),
-            TokenType::Number(_) => {
+            TokenType::Label(_) => return self.label(),
+            TokenType::Number(_) | TokenType::Negative(_) | TokenType::Anonymous(..) => {
                 return Err(Box::new(SyntaxError(
                     "You cant just randomly put numbers anywhere you like you know".to_string(),
                     self.current_token().position.clone(),
                 )))
             }
+            TokenType::Str(_) => {
+                return Err(Box::new(SyntaxError(
+                    "You cant just randomly put strings anywhere you like you know".to_string(),
+                    self.current_token().position.clone(),
+                )))
+            }
+            TokenType::Pixels(_) => {
+                return Err(Box::new(SyntaxError(
+                    "Rows of '#' and '.' only go in a SPRITE block".to_string(),
+                    self.current_token().position.clone(),
+                )))
+            }
             TokenType::Register(_) => {
                 return Err(Box::new(SyntaxError(
                     "You cant just randomly put registers anywhere you like you know".to_string(),
@@ -143,36 +377,52 @@ impl Parser {
     }
 
     fn statement(&mut self) -> Result<()> {
-        let ignore = self.ignore();
         match self.current_token().token {
-            TokenType::Number(_) if !ignore => {
+            TokenType::Number(_) | TokenType::Negative(_) | TokenType::Anonymous(..) => {
                 return Err(Box::new(SyntaxError(
                     "You cant just randomly put numbers anywhere you like you know".to_string(),
                     self.current_token().position.clone(),
                 )))
             }
-            TokenType::Register(_) if !ignore => {
+            TokenType::Str(_) => {
+                return Err(Box::new(SyntaxError(
+                    "You cant just randomly put strings anywhere you like you know".to_string(),
+                    self.current_token().position.clone(),
+                )))
+            }
+            TokenType::Pixels(_) => {
+                return Err(Box::new(SyntaxError(
+                    "Rows of '#' and '.' only go in a SPRITE block".to_string(),
+                    self.current_token().position.clone(),
+                )))
+            }
+            TokenType::Register(_) => {
                 return Err(Box::new(SyntaxError(
                     "You cant just randomly put registers anywhere you like you know".to_string(),
                     self.current_token().position.clone(),
                 )))
             }
-            TokenType::Command(_) if !ignore => return self.command(),
-            TokenType::Label(ref l) if !ignore => {
+            TokenType::Command(_) => return self.command(),
+            TokenType::Label(ref l) => {
                 let l = l.clone();
                 if let Some(k) = self.labels.get_mut(&l) {
-                    *k = self.instructions_len;
+                    *k = self.address;
                 }
                 self.advance(); // colon
             }
-            TokenType::MprocessorDirective(_) => return self.m_process(),
             TokenType::Comment(_) => (),
-            TokenType::Colon if !ignore => {
+            TokenType::Colon => {
                 return Err(Box::new(SyntaxError(
                     "You cant just randomly put colons anywhere you like you know".to_string(),
                     self.current_token().position.clone(),
                 )))
             }
+            TokenType::Comparison(_) => {
+                return Err(Box::new(SyntaxError(
+                    "Comparisons only go in 'if' and 'while' conditions".to_string(),
+                    self.current_token().position.clone(),
+                )))
+            }
             TokenType::Eof => unreachable!(),
             TokenType::Eol => (),
             _ => (),
@@ -181,12 +431,67 @@ impl Parser {
         Ok(())
     }
 
+    /// Records the namespace an included file goes in. Files included without one
+    /// join the namespace of the file including them.
+    fn namespace(
+        &mut self,
+        file: &str,
+        namespace: Option<&str>,
+        position: &Position,
+    ) -> Result<()> {
+        let namespace = match namespace {
+            Some(namespace)
+                if !namespace.is_empty()
+                    && namespace
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+            {
+                self.identifier(namespace)
+            }
+            Some(namespace) => {
+                return Err(Box::new(MprocessorException(
+                    format!(
+                        "Invalid namespace '{}', expected letters, digits and underscores",
+                        namespace
+                    ),
+                    position.clone(),
+                )))
+            }
+            None => match self.namespaces.files.get(position.file.as_str()) {
+                Some(namespace) => namespace.clone(),
+                None => return Ok(()),
+            },
+        };
+        match self.namespaces.files.get(file) {
+            Some(old) if *old != namespace => Err(Box::new(MprocessorException(
+                format!(
+                    "{} is already included as {}, it can't also be {}",
+                    file, old, namespace
+                ),
+                position.clone(),
+            ))),
+            _ => {
+                self.namespaces.files.insert(file.to_string(), namespace);
+                Ok(())
+            }
+        }
+    }
+
+    /// A name as the lexer would spell it
+    fn identifier(&self, name: &str) -> String {
+        if self.lexer.case_sensitive {
+            name.to_string()
+        } else {
+            name.to_ascii_lowercase()
+        }
+    }
+
     fn m_process(&mut self) -> Result<()> {
         let ignore = self.ignore();
         let dir = self.current_token().clone();
         self.advance();
         let arg = if let TokenType::Comment(ref c) = self.current_token().token {
-            c.trim()
+            c.trim().to_string()
         } else {
             return Err(Box::new(SyntaxError(
                 "Expected ';' after a preprocessor directive".to_string(),
@@ -196,54 +501,109 @@ impl Parser {
 
         if let TokenType::MprocessorDirective(ref p) = dir.token {
             match p {
-                MprocessorDirective::M_include if !ignore => match fs::read_to_string(arg) {
-                    Ok(code) => {
-                        let tokens = lex(&code, Rc::new(arg.to_string()))?;
-                        self.tokens
-                            .splice(self.current_token - 1..=self.current_token, tokens);
-                        self.current_token -= 1;
-                        return Ok(());
+                MprocessorDirective::M_include if !ignore => {
+                    let (file, namespace) = match arg.rsplit_once(" as ") {
+                        Some((file, namespace)) => {
+                            (file.trim().to_string(), Some(namespace.trim()))
+                        }
+                        None => (arg.clone(), None),
+                    };
+                    if let Some(namespace) = namespace.filter(|_| self.preprocess_only) {
+                        return Err(Box::new(MprocessorException(
+                            format!(
+                                "-E can't keep the namespace '{}', the expanded source has no includes to put it on. Assemble without -E instead",
+                                namespace
+                            ),
+                            dir.position.clone(),
+                        )));
                     }
-                    Err(e) => {
-                        return Err(Box::new(FileException(
-                            format!("Could not read file: {}", e),
-                            self.current_token().position.clone(),
-                        )))
+                    match fs::read_to_string(&file) {
+                        Ok(code) => {
+                            self.namespace(&file, namespace, &dir.position)?;
+                            let mut tokens = lex(
+                                &code,
+                                Rc::new(file),
+                                &self.lexer,
+                                &mut self.diagnostics,
+                                &mut self.spellings,
+                            )?;
+                            tokens.pop(); // the included file's Eof
+                            self.tokens
+                                .splice(self.current_token - 1..=self.current_token, tokens);
+                            self.current_token -= 1;
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            return Err(Box::new(FileException(
+                                format!("Could not read file: {}", e),
+                                self.current_token().position.clone(),
+                            )))
+                        }
                     }
-                },
+                }
                 MprocessorDirective::M_error if !ignore => {
                     return Err(Box::new(SyntaxError(
-                        arg.to_string(),
+                        arg,
                         self.current_token().position.clone(),
                     )))
                 }
-                MprocessorDirective::M_define if !ignore => {
-                    let arg = arg.to_string();
-                    self.defined.insert(arg);
-                    self.tokens
-                        .drain(self.current_token - 1..=self.current_token);
-                    self.current_token -= 1;
-                    return Ok(());
+                MprocessorDirective::M_warning if !ignore => {
+                    self.diagnostics
+                        .push(Box::new(Warning(arg, dir.position.clone())));
+                }
+                MprocessorDirective::M_info if !ignore => {
+                    self.diagnostics
+                        .push(Box::new(Info(arg, dir.position.clone())));
                 }
-                MprocessorDirective::M_undef if !ignore => {
-                    let arg = arg.to_string();
-                    if !self.defined.remove(&arg) {
-                        return Err(Box::new(Undefined(
-                            format!("Undefined flag: {}", arg),
+                MprocessorDirective::M_assert if !ignore => {
+                    let radix = self
+                        .radixes
+                        .get(dir.position.file.as_str())
+                        .copied()
+                        .unwrap_or(self.lexer.radix);
+                    self.asserts
+                        .push((arg, dir.position.clone(), self.defined.clone(), radix));
+                }
+                MprocessorDirective::M_export if !ignore => {
+                    let names = arg
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|name| !name.is_empty())
+                        .map(|name| (self.identifier(name), dir.position.clone()))
+                        .collect::<Vec<_>>();
+                    self.namespaces
+                        .exports
+                        .entry(dir.position.file.to_string())
+                        .or_default()
+                        .extend(names);
+                }
+                // The lexer has already checked it
+                MprocessorDirective::M_radix => {
+                    if let Ok(radix) = arg.parse() {
+                        self.radixes.insert(dir.position.file.to_string(), radix);
+                    }
+                }
+                MprocessorDirective::M_define if !ignore => {
+                    let (name, value) = split_define(&arg);
+                    if name.is_empty() {
+                        return Err(Box::new(MprocessorException(
+                            format!("Invalid flag '{}', expected NAME or NAME=VALUE", arg),
                             self.current_token().position.clone(),
                         )));
                     }
-                    self.tokens
-                        .drain(self.current_token - 1..=self.current_token);
-                    self.current_token -= 1;
-                    return Ok(());
+                    self.defined.insert(name.to_string(), value.to_string());
+                }
+                MprocessorDirective::M_undef if !ignore && self.defined.remove(&arg).is_none() => {
+                    return Err(Box::new(Undefined(
+                        format!("Undefined flag: {}", arg),
+                        self.current_token().position.clone(),
+                    )));
                 }
                 MprocessorDirective::M_ifdef => {
-                    let b = self.defined.contains(arg);
+                    let b = self.is_defined(&arg, &dir.position);
                     self.ifs.push(b);
                 }
                 MprocessorDirective::M_ifndef => {
-                    let b = !self.defined.contains(arg);
+                    let b = !self.is_defined(&arg, &dir.position);
                     self.ifs.push(b);
                 }
                 MprocessorDirective::M_else => {
@@ -256,13 +616,11 @@ impl Parser {
                         )));
                     }
                 }
-                MprocessorDirective::M_endif => {
-                    if self.ifs.pop().is_none() {
-                        return Err(Box::new(SyntaxError(
-                            "%?- without any %?# or %?!".to_string(),
-                            self.current_token().position.clone(),
-                        )));
-                    }
+                MprocessorDirective::M_endif if self.ifs.pop().is_none() => {
+                    return Err(Box::new(SyntaxError(
+                        "%?- without any %?# or %?!".to_string(),
+                        self.current_token().position.clone(),
+                    )));
                 }
                 _ => (),
             }
@@ -271,6 +629,27 @@ impl Parser {
         Ok(())
     }
 
+    /// `NAME` checks that a flag is defined, `NAME=VALUE` also checks its value
+    /// Whether the flag is defined, or has the value after `=`. Numbers are compared
+    /// by value in the radix of the file, so `0x600` matches `0x0600`.
+    fn is_defined(&self, arg: &str, position: &Position) -> bool {
+        let radix = self
+            .radixes
+            .get(position.file.as_str())
+            .copied()
+            .unwrap_or(self.lexer.radix);
+        match arg.split_once('=') {
+            Some((name, value)) => self.defined.get(name.trim()).is_some_and(|defined| {
+                let value = value.trim();
+                match (parse_number(defined, radix), parse_number(value, radix)) {
+                    (Some(defined), Some(value)) => defined == value,
+                    _ => defined == value,
+                }
+            }),
+            None => self.defined.contains_key(arg),
+        }
+    }
+
     fn command(&mut self) -> Result<()> {
         let command = self.current_token().clone();
         self.advance();
@@ -291,7 +670,10 @@ impl Parser {
             if let TokenType::Label(ref l) = self.current_token().token {
                 if !self.labels.contains_key(l) {
                     return Err(Box::new(Undefined(
-                        format!("label '{}' is not defined anywhere", l),
+                        format!(
+                            "label '{}' is not defined anywhere",
+                            self.spelling(l, &self.current_token().position)
+                        ),
                         self.current_token().position.clone(),
                     )));
                 }
@@ -306,16 +688,147 @@ impl Parser {
                     command.position.clone(),
                 )));
             }
-            if c == Command::CHIP {
-                self.instructions_len += args.len();
-            } else {
-                self.instructions_len += 1;
+            if c.target(&args) > self.target {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "{:?} needs the {} target, but the target is {}",
+                        c,
+                        c.target(&args).name(),
+                        self.target.name()
+                    ),
+                    command.position.clone(),
+                )));
             }
-            self.instructions.push((c, args));
+            // HALT jumps to itself, which only the layout knows the address of
+            let (c, args) = match c {
+                Command::HALT if self.address > 0xFFF => {
+                    return Err(Box::new(NumberOverflow(
+                        format!(
+                            "HALT is at 0x{:X}, which does not fit in JMP's 12 bit address",
+                            self.address
+                        ),
+                        command.position.clone(),
+                    )))
+                }
+                Command::HALT => (Command::JMP, vec![TokenType::Number(self.address as u16)]),
+                c => (c, args),
+            };
+            let size = match (&c, args.as_slice()) {
+                (Command::ORG, &[TokenType::Number(address)]) => {
+                    if (address as usize) < self.start {
+                        return Err(Box::new(SyntaxError(
+                            format!(
+                                "Cannot place code at 0x{:03X}, before the program starts at 0x{:03X}",
+                                address, self.start
+                            ),
+                            command.position.clone(),
+                        )));
+                    }
+                    self.address = address as usize;
+                    return Ok(());
+                }
+                (Command::ALIGN, &[TokenType::Number(n)]) => {
+                    self.address = self.address.next_multiple_of(n as usize);
+                    return Ok(());
+                }
+                (Command::RESERVE, &[TokenType::Number(n)]) => {
+                    self.regions.push((
+                        self.address,
+                        self.address + n as usize,
+                        Some(command.position.clone()),
+                    ));
+                    self.address += n as usize;
+                    return Ok(());
+                }
+                (Command::LONGPOINT, _) => {
+                    self.long_after_skip(&c, 4, &command.position)?;
+                    4
+                }
+                // Data longer than a word would be run from its third byte on
+                (Command::CHIP | Command::BYTE, _) if c.size(&args) > 2 => {
+                    let size = c.size(&args);
+                    self.long_after_skip(&c, size, &command.position)?;
+                    size
+                }
+                _ => c.size(&args),
+            };
+            self.regions.push((
+                self.address,
+                self.address + size,
+                Some(command.position.clone()),
+            ));
+            self.instructions
+                .push((self.address, c, args, command.position.clone()));
+            self.address += size;
         }
         Ok(())
     }
 
+    /// A skip only jumps over the next two bytes, so it lands in the middle
+    /// of a longer instruction or data statement that follows it
+    fn long_after_skip(&mut self, next: &Command, size: usize, position: &Position) -> Result<()> {
+        let address = self.address;
+        let skip = match self.instructions.last_mut() {
+            Some((skip_address, skip, _, _)) if *skip_address + 2 == address && skip.is_skip() => {
+                skip
+            }
+            _ => return Ok(()),
+        };
+        match self.long_skip {
+            LongSkip::Native => Ok(()),
+            LongSkip::Error => Err(Box::new(SkipHazard(
+                format!(
+                    "{:?} only skips the first two bytes of the {} byte {:?} after it. Use --long-skip rewrite to jump over it instead, or --long-skip native if the interpreter skips the whole instruction",
+                    skip, size, next
+                ),
+                position.clone(),
+            ))),
+            LongSkip::Rewrite => {
+                // JMPEQ v0 1, LONGPOINT x becomes JMPNE v0 1, JMP after, LONGPOINT x
+                let after = address + 2 + size;
+                if after > 0xFFF {
+                    return Err(Box::new(SkipHazard(
+                        format!(
+                            "Cannot jump over the instruction after {:?}, 0x{:X} does not fit in JMP's 12 bit address",
+                            skip, after
+                        ),
+                        position.clone(),
+                    )));
+                }
+                *skip = skip.inverse_skip();
+                for label in self.labels.values_mut() {
+                    if *label == address {
+                        *label += 2;
+                    }
+                }
+                self.regions
+                    .push((address, address + 2, Some(position.clone())));
+                self.instructions.push((
+                    address,
+                    Command::JMP,
+                    vec![TokenType::Number(after as u16)],
+                    position.clone(),
+                ));
+                self.address += 2;
+                Ok(())
+            }
+        }
+    }
+
+    /// `label` as it was written at `position`. Local and namespaced labels were
+    /// written as the end of their full name.
+    fn spelling(&self, label: &str, position: &Position) -> String {
+        self.spellings
+            .get(position)
+            .filter(|spelling| {
+                label
+                    .to_ascii_lowercase()
+                    .ends_with(&spelling.to_ascii_lowercase())
+            })
+            .cloned()
+            .unwrap_or_else(|| label.to_string())
+    }
+
     fn label(&mut self) -> Result<()> {
         let label = if let TokenType::Label(ref l) = self.current_token().token {
             l.clone()
@@ -330,18 +843,32 @@ impl Parser {
             )));
         }
 
+        if self.lexer.case_sensitive {
+            if let Some(other) = self
+                .labels
+                .keys()
+                .find(|other| other.eq_ignore_ascii_case(&label) && **other != label)
+            {
+                self.diagnostics.push(Box::new(Warning(
+                    format!(
+                        "Label '{}' only differs from '{}' in case, which is easy to mix up",
+                        label, other
+                    ),
+                    self.current_token().position.clone(),
+                )));
+            }
+        }
+
+        let spelling = self.spelling(&label, &self.current_token().position);
         match self.labels.entry(label) {
             Entry::Vacant(e) => {
-                if e.key() == "main" {
-                    self.has_main = true;
-                }
                 e.insert(0);
             }
-            Entry::Occupied(e) => {
-                return Err(Box::new(SyntaxError(
+            Entry::Occupied(_) => {
+                return Err(Box::new(Redefinition(
                     format!(
                         "Label '{}' has already been defined and cannot be redefined",
-                        e.key()
+                        spelling
                     ),
                     self.current_token().position.clone(),
                 )));
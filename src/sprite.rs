@@ -0,0 +1,692 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    exception::{
+        Exception, FileException, NumberOverflow, Position, Redefinition, Result, SyntaxError,
+        Undefined,
+    },
+    font::Font,
+    image,
+    token::{Command, Token, TokenType},
+};
+
+/// Rows DRAW can draw from an 8 pixel wide sprite, N is only 4 bits
+const MAX_HEIGHT: usize = 0xF;
+/// Rows of a 16 pixel wide sprite, which DRAW always draws whole with N = 0
+const WIDE_HEIGHT: usize = 16;
+
+struct Sprite {
+    name: String,
+    position: Position,
+    rows: Vec<(String, Position)>,
+}
+
+struct Tilemap {
+    name: String,
+    position: Position,
+    /// The tile each character stands for, a number or a tile label
+    legend: HashMap<char, (TokenType, Position)>,
+    rows: Vec<(String, Position)>,
+}
+
+struct Sprites {
+    output: Vec<Token>,
+    /// Constants such as `ship.height`, with the sprite that made them
+    constants: HashMap<String, (u16, Position)>,
+    /// Fonts made with `FONT`, for `TEXT` after them
+    fonts: HashMap<String, Font>,
+    /// The `SPRITE` block being read
+    open: Option<Sprite>,
+    /// The `TILEMAP` block being read
+    tilemap: Option<Tilemap>,
+    /// The index of every tile of an `IMAGE` sheet or `TEXT`, by its label
+    tiles: HashMap<String, u16>,
+    /// Tilemap cells given as a tile label, by where they are in the output
+    cells: Vec<usize>,
+}
+
+/// Turns `SPRITE name` blocks, drawn a row per line with `#` and `.`, `IMAGE`
+/// files and `TEXT` strings into labels and the bytes of each row, and `TILEMAP`
+/// blocks into a byte per tile. Each one also makes constants such as
+/// `name.height`, the N to DRAW it with, which are replaced wherever they are used.
+pub fn sprites(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut sprites = Sprites {
+        output: Vec::new(),
+        constants: HashMap::new(),
+        fonts: HashMap::new(),
+        open: None,
+        tilemap: None,
+        tiles: HashMap::new(),
+        cells: Vec::new(),
+    };
+    let mut line = Vec::new();
+    for token in tokens {
+        let end = matches!(token.token, TokenType::Eol | TokenType::Eof);
+        line.push(token);
+        if end {
+            sprites.line(mem::take(&mut line))?;
+        }
+    }
+    if let Some(sprite) = sprites.open {
+        return Err(Box::new(SyntaxError(
+            format!("Sprite '{}' was never closed with 'end'", sprite.name),
+            sprite.position,
+        )));
+    }
+    if let Some(tilemap) = sprites.tilemap {
+        return Err(Box::new(SyntaxError(
+            format!("Tilemap '{}' was never closed with 'end'", tilemap.name),
+            tilemap.position,
+        )));
+    }
+
+    let mut output = sprites.output;
+    // Tiles can be used before the sheet they are in
+    for &i in &sprites.cells {
+        let TokenType::Label(tile) = &output[i].token else {
+            unreachable!()
+        };
+        output[i].token = match sprites.tiles.get(tile) {
+            Some(&index) if index <= 0xFF => TokenType::Number(index),
+            Some(&index) => {
+                return Err(Box::new(NumberOverflow(
+                    format!(
+                        "Tile '{}' is number {}, which doesn't fit in a byte",
+                        tile, index
+                    ),
+                    output[i].position.clone(),
+                )))
+            }
+            None => {
+                return Err(Box::new(Undefined(
+                    format!("'{}' is not a tile of an IMAGE sheet or a TEXT", tile),
+                    output[i].position.clone(),
+                )))
+            }
+        };
+    }
+    for i in 0..output.len() {
+        if let TokenType::Label(name) = &output[i].token {
+            let definition = output.get(i + 1).map(|t| &t.token) == Some(&TokenType::Colon);
+            if let (Some(&(value, _)), false) = (sprites.constants.get(name), definition) {
+                output[i].token = TokenType::Number(value);
+            }
+        }
+    }
+    Ok(output)
+}
+
+impl Sprites {
+    fn line(&mut self, mut line: Vec<Token>) -> Result<()> {
+        let end = line.pop().unwrap();
+        let significant = line
+            .iter()
+            .filter(|t| !matches!(t.token, TokenType::Comment(_)))
+            .collect::<Vec<_>>();
+        if self.tilemap.is_some() {
+            self.tilemap_line(&significant)?;
+            self.output.push(end);
+            return Ok(());
+        }
+        match (&mut self.open, significant.as_slice()) {
+            (Some(_), []) => (),
+            (
+                Some(sprite),
+                [Token {
+                    token: TokenType::Pixels(row),
+                    position,
+                }],
+            ) => sprite.rows.push((row.clone(), position.clone())),
+            (
+                Some(_),
+                [Token {
+                    token: TokenType::Label(word),
+                    ..
+                }],
+            ) if word == "end" => {
+                let sprite = self.open.take().unwrap();
+                let height = self.rows(&sprite)?;
+                self.define(&sprite.name, "height", height, &sprite.position)?;
+                self.align(&sprite.position);
+            }
+            (Some(sprite), [token, ..]) => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "Expected a row of '#' and '.' or 'end' in sprite '{}'",
+                        sprite.name
+                    ),
+                    token.position.clone(),
+                )))
+            }
+            (None, _) => match line.iter().position(|t| {
+                matches!(
+                    t.token,
+                    TokenType::Command(
+                        Command::SPRITE
+                            | Command::IMAGE
+                            | Command::FONT
+                            | Command::TEXT
+                            | Command::TILEMAP
+                    )
+                )
+            }) {
+                Some(start) => {
+                    let head = line[start].clone();
+                    let args = line
+                        .drain(start..)
+                        .skip(1)
+                        .map(|t| t.token)
+                        .filter(|t| !matches!(t, TokenType::Comment(_)))
+                        .collect::<Vec<_>>();
+                    // Labels before it stay, at the same address as the sprite
+                    self.output.extend(line);
+                    let TokenType::Command(command) = head.token else {
+                        unreachable!()
+                    };
+                    self.command(command, &args, head.position)?;
+                }
+                None => self.output.extend(line),
+            },
+        }
+        self.output.push(end);
+        Ok(())
+    }
+
+    fn command(&mut self, command: Command, args: &[TokenType], position: Position) -> Result<()> {
+        if let [TokenType::Number(n), ..] = args {
+            return Err(Box::new(SyntaxError(
+                format!(
+                    "{:?} names can't be made of hex digits only, this one reads as 0x{:X}",
+                    command, n
+                ),
+                position,
+            )));
+        }
+        if !command.is_valid_chip8_instruction(args) {
+            return Err(Box::new(SyntaxError(
+                match command {
+                    Command::SPRITE => "Expected SPRITE NAME, with the rows on the lines below",
+                    Command::IMAGE => "Expected IMAGE NAME \"FILE\", or IMAGE NAME \"FILE\" N for a sheet of 8xN or 16x16 tiles",
+                    Command::FONT => "Expected FONT NAME \"FILE\" N \"CHARACTERS\", for a sheet of 8xN glyphs",
+                    Command::TEXT => "Expected TEXT NAME \"TEXT\", or TEXT NAME \"TEXT\" FONT",
+                    _ => "Expected TILEMAP NAME, with the legend and rows on the lines below",
+                }
+                .to_string(),
+                position,
+            )));
+        }
+        match (command, args) {
+            (Command::SPRITE, [TokenType::Label(name)]) => {
+                self.open = Some(Sprite {
+                    name: name.clone(),
+                    position,
+                    rows: Vec::new(),
+                })
+            }
+            (Command::TILEMAP, [TokenType::Label(name)]) => {
+                self.tilemap = Some(Tilemap {
+                    name: name.clone(),
+                    position,
+                    legend: HashMap::new(),
+                    rows: Vec::new(),
+                })
+            }
+            (Command::IMAGE, [TokenType::Label(name), TokenType::Str(path), rest @ ..]) => {
+                let tile = match rest {
+                    [TokenType::Number(n)] => Some(*n as usize),
+                    _ => None,
+                };
+                self.image(name, path, tile, &position)?
+            }
+            (
+                Command::FONT,
+                [TokenType::Label(name), TokenType::Str(path), TokenType::Number(height), TokenType::Str(chars)],
+            ) => self.font(name, path, *height as usize, chars, &position)?,
+            (Command::TEXT, [TokenType::Label(name), TokenType::Str(text), rest @ ..]) => {
+                let font = match rest {
+                    [TokenType::Label(font)] => Some(font.as_str()),
+                    _ => None,
+                };
+                self.text(name, text, font, &position)?
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Adds the constant `name.suffix`, as long as no other sprite made it
+    fn define(&mut self, name: &str, suffix: &str, value: u16, position: &Position) -> Result<()> {
+        let constant = format!("{}.{}", name, suffix);
+        if let Some((_, defined)) = self.constants.get(&constant) {
+            return Err(Box::new(Redefinition(
+                format!("Sprite '{}' is already defined at {}", name, defined),
+                position.clone(),
+            )));
+        }
+        self.constants.insert(constant, (value, position.clone()));
+        Ok(())
+    }
+
+    /// Lays out an image as one sprite, or with `tile` as a sheet of 8 by `tile`
+    /// tiles, 16 by 16 when `tile` is 16. Each tile is labelled `name.0`, `name.1` and
+    /// so on, left to right then top to bottom.
+    fn image(
+        &mut self,
+        name: &str,
+        path: &str,
+        tile: Option<usize>,
+        position: &Position,
+    ) -> Result<()> {
+        let pixels = load(path, position)?;
+        let (width, height) = (pixels.first().map_or(0, Vec::len), pixels.len());
+        let Some(tile) = tile else {
+            if width != 8 && width != 16 {
+                return Err(Box::new(FileException(
+                    format!(
+                        "'{}' is {}x{}, a sprite must be 8 or 16 pixels wide. Give a tile height to slice it into tiles",
+                        path, width, height
+                    ),
+                    position.clone(),
+                )));
+            }
+            let sprite = Sprite {
+                name: name.to_string(),
+                position: position.clone(),
+                rows: rows(&pixels, 0, width, position),
+            };
+            let n = self.rows(&sprite)?;
+            self.align(position);
+            return self.define(name, "height", n, position);
+        };
+
+        let tile_width = if tile == WIDE_HEIGHT { 16 } else { 8 };
+        let tiles = tiles(&pixels, tile_width, tile, path, position)?;
+        self.statement(
+            vec![TokenType::Label(name.to_string()), TokenType::Colon],
+            position,
+        );
+        for (i, rows) in tiles.iter().enumerate() {
+            self.tiles.insert(format!("{}.{}", name, i), i as u16);
+            let tile = Sprite {
+                name: format!("{}.{}", name, i),
+                position: position.clone(),
+                rows: rows.clone(),
+            };
+            self.rows(&tile)?;
+        }
+        self.align(position);
+        self.define(name, "height", (tile % 16) as u16, position)?;
+        self.define(name, "count", tiles.len() as u16, position)
+    }
+
+    /// Reads a sheet of 8 by `height` glyphs, one for each of `chars` in order
+    fn font(
+        &mut self,
+        name: &str,
+        path: &str,
+        height: usize,
+        chars: &str,
+        position: &Position,
+    ) -> Result<()> {
+        let pixels = load(path, position)?;
+        let tiles = tiles(&pixels, 8, height, path, position)?;
+        if chars.chars().count() > tiles.len() {
+            return Err(Box::new(FileException(
+                format!(
+                    "'{}' only has {} glyphs for the {} characters of font '{}'",
+                    path,
+                    tiles.len(),
+                    chars.chars().count(),
+                    name
+                ),
+                position.clone(),
+            )));
+        }
+        let glyphs = chars.chars().zip(tiles).map(|(c, rows)| {
+            let bytes = rows.iter().map(|(row, _)| bits(row) as u8).collect();
+            (c, bytes)
+        });
+        if self
+            .fonts
+            .insert(name.to_string(), Font::new(height, glyphs))
+            .is_some()
+        {
+            return Err(Box::new(Redefinition(
+                format!("Font '{}' is already defined", name),
+                position.clone(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Places a glyph per character of `text`, labelled `name.0`, `name.1` and so on
+    fn text(
+        &mut self,
+        name: &str,
+        text: &str,
+        font: Option<&str>,
+        position: &Position,
+    ) -> Result<()> {
+        let builtin;
+        let font = match font {
+            Some(font) => match self.fonts.get(font) {
+                Some(font) => font,
+                None => {
+                    return Err(Box::new(Undefined(
+                        format!(
+                            "There is no font '{}', make it with FONT before using it",
+                            font
+                        ),
+                        position.clone(),
+                    )))
+                }
+            },
+            None => {
+                builtin = Font::builtin();
+                &builtin
+            }
+        };
+        let mut glyphs = Vec::new();
+        for c in text.chars() {
+            match font.glyph(c) {
+                Some(glyph) => glyphs.push(glyph.to_vec()),
+                None => {
+                    return Err(Box::new(SyntaxError(
+                        format!("The font has no glyph for {:?}", c),
+                        position.clone(),
+                    )))
+                }
+            }
+        }
+        let (height, width) = (font.height, font.width());
+
+        self.statement(
+            vec![TokenType::Label(name.to_string()), TokenType::Colon],
+            position,
+        );
+        for (i, glyph) in glyphs.into_iter().enumerate() {
+            self.tiles.insert(format!("{}.{}", name, i), i as u16);
+            self.statement(
+                vec![
+                    TokenType::Label(format!("{}.{}", name, i)),
+                    TokenType::Colon,
+                ],
+                position,
+            );
+            self.statement(
+                std::iter::once(TokenType::Command(Command::BYTE))
+                    .chain(glyph.into_iter().map(|row| TokenType::Number(row as u16)))
+                    .collect(),
+                position,
+            );
+        }
+        self.align(position);
+        self.define(name, "height", height as u16, position)?;
+        self.define(name, "width", width as u16, position)?;
+        self.define(name, "count", text.chars().count() as u16, position)
+    }
+
+    /// Reads a line of a `TILEMAP` block: a legend entry, a row of the map or `end`
+    fn tilemap_line(&mut self, line: &[&Token]) -> Result<()> {
+        let tilemap = self.tilemap.as_mut().unwrap();
+        match line {
+            [] => (),
+            [Token {
+                token: TokenType::Str(row),
+                position,
+            }] => tilemap.rows.push((row.clone(), position.clone())),
+            [key, tile @ Token {
+                token: TokenType::Number(_) | TokenType::Label(_),
+                ..
+            }] => {
+                let c = match &key.token {
+                    TokenType::Number(n @ 0..=0x7F) => char::from(*n as u8),
+                    TokenType::Str(s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+                    _ => {
+                        return Err(Box::new(SyntaxError(
+                            "Expected a single character like '#' for the legend".to_string(),
+                            key.position.clone(),
+                        )))
+                    }
+                };
+                if let TokenType::Number(n @ 0x100..) = tile.token {
+                    return Err(Box::new(NumberOverflow(
+                        format!("Tile 0x{:X} doesn't fit in a byte", n),
+                        tile.position.clone(),
+                    )));
+                }
+                if let Some((_, defined)) = tilemap.legend.get(&c) {
+                    return Err(Box::new(Redefinition(
+                        format!("{:?} is already in the legend at {}", c, defined),
+                        key.position.clone(),
+                    )));
+                }
+                tilemap
+                    .legend
+                    .insert(c, (tile.token.clone(), key.position.clone()));
+            }
+            [Token {
+                token: TokenType::Label(word),
+                ..
+            }] if word == "end" => {
+                let tilemap = self.tilemap.take().unwrap();
+                self.map(tilemap)?;
+            }
+            [token, ..] => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "Expected a legend entry like '#' wall.0, a row like \"#..#\" or 'end' in tilemap '{}'",
+                        tilemap.name
+                    ),
+                    token.position.clone(),
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the tilemap's label and a byte per cell, a row per line
+    fn map(&mut self, tilemap: Tilemap) -> Result<()> {
+        let width = match tilemap.rows.first() {
+            Some((row, _)) => row.chars().count(),
+            None => {
+                return Err(Box::new(SyntaxError(
+                    format!("Tilemap '{}' has no rows", tilemap.name),
+                    tilemap.position.clone(),
+                )))
+            }
+        };
+        self.statement(
+            vec![TokenType::Label(tilemap.name.clone()), TokenType::Colon],
+            &tilemap.position,
+        );
+        for (row, position) in &tilemap.rows {
+            if row.chars().count() != width {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "Every row of tilemap '{}' must be {} tiles wide like the first, this one is {}",
+                        tilemap.name,
+                        width,
+                        row.chars().count()
+                    ),
+                    position.clone(),
+                )));
+            }
+            let mut cells = vec![TokenType::Command(Command::BYTE)];
+            for c in row.chars() {
+                match tilemap.legend.get(&c) {
+                    Some((tile, _)) => cells.push(tile.clone()),
+                    None => {
+                        return Err(Box::new(Undefined(
+                            format!("{:?} is not in the legend of tilemap '{}'", c, tilemap.name),
+                            position.clone(),
+                        )))
+                    }
+                }
+            }
+            let start = self.output.len();
+            self.statement(cells, position);
+            for (i, token) in self.output.iter().enumerate().skip(start) {
+                if matches!(token.token, TokenType::Label(_)) {
+                    self.cells.push(i);
+                }
+            }
+        }
+        self.align(&tilemap.position);
+        self.define(&tilemap.name, "width", width as u16, &tilemap.position)?;
+        self.define(
+            &tilemap.name,
+            "height",
+            tilemap.rows.len() as u16,
+            &tilemap.position,
+        )
+    }
+
+    /// Writes the sprite's label and a row of data per line, returning the N to draw it with
+    fn rows(&mut self, sprite: &Sprite) -> Result<u16> {
+        let width = match sprite.rows.first() {
+            Some((row, _)) if row.len() == 8 || row.len() == 16 => row.len(),
+            Some((row, position)) => {
+                return Err(Box::new(SyntaxError(
+                    format!("Sprite rows are 8 or 16 pixels wide, not {}", row.len()),
+                    position.clone(),
+                )))
+            }
+            None => {
+                return Err(Box::new(SyntaxError(
+                    format!("Sprite '{}' has no rows", sprite.name),
+                    sprite.position.clone(),
+                )))
+            }
+        };
+        if let Some((row, position)) = sprite.rows.iter().find(|(row, _)| row.len() != width) {
+            return Err(Box::new(SyntaxError(
+                format!(
+                    "Every row of sprite '{}' must be {} pixels wide like the first, this one is {}",
+                    sprite.name,
+                    width,
+                    row.len()
+                ),
+                position.clone(),
+            )));
+        }
+        let height = sprite.rows.len();
+        let n = match width {
+            8 if height <= MAX_HEIGHT => height as u16,
+            8 => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "Sprite '{}' is {} rows tall, but DRAW can only draw up to {}",
+                        sprite.name, height, MAX_HEIGHT
+                    ),
+                    sprite.position.clone(),
+                )))
+            }
+            _ if height == WIDE_HEIGHT => 0,
+            _ => {
+                return Err(Box::new(SyntaxError(
+                    format!(
+                        "Sprite '{}' is 16 pixels wide, so it must be {} rows tall, not {}",
+                        sprite.name, WIDE_HEIGHT, height
+                    ),
+                    sprite.position.clone(),
+                )))
+            }
+        };
+
+        self.statement(
+            vec![TokenType::Label(sprite.name.clone()), TokenType::Colon],
+            &sprite.position,
+        );
+        for (row, position) in &sprite.rows {
+            let command = match width {
+                8 => Command::BYTE,
+                _ => Command::CHIP,
+            };
+            self.statement(
+                vec![TokenType::Command(command), TokenType::Number(bits(row))],
+                position,
+            );
+        }
+        Ok(n)
+    }
+
+    /// Byte data can end at an odd address, so the code after it starts on the next word
+    fn align(&mut self, position: &Position) {
+        self.statement(
+            vec![TokenType::Command(Command::ALIGN), TokenType::Number(2)],
+            position,
+        );
+    }
+
+    fn statement(&mut self, tokens: Vec<TokenType>, position: &Position) {
+        for token in tokens.into_iter().chain([TokenType::Eol]) {
+            self.output.push(Token {
+                token,
+                position: position.clone(),
+            });
+        }
+    }
+}
+
+fn load(path: &str, position: &Position) -> Result<Vec<Vec<bool>>> {
+    image::load(path)
+        .map_err(|e| Box::new(FileException(e, position.clone())) as Box<dyn Exception>)
+}
+
+/// Slices an image into `width` by `height` tiles, left to right then top to bottom
+fn tiles(
+    pixels: &[Vec<bool>],
+    width: usize,
+    height: usize,
+    path: &str,
+    position: &Position,
+) -> Result<Vec<Vec<(String, Position)>>> {
+    let (image_width, image_height) = (pixels.first().map_or(0, Vec::len), pixels.len());
+    if image_width == 0
+        || image_width % width != 0
+        || image_height == 0
+        || image_height % height != 0
+    {
+        return Err(Box::new(FileException(
+            format!(
+                "'{}' is {}x{}, which doesn't divide into {}x{} tiles",
+                path, image_width, image_height, width, height
+            ),
+            position.clone(),
+        )));
+    }
+    Ok(pixels
+        .chunks(height)
+        .flat_map(|band| {
+            (0..image_width)
+                .step_by(width)
+                .map(move |x| rows(band, x, width, position))
+        })
+        .collect())
+}
+
+/// The pixels `x` to `x + width` of each row, as `#` and `.`
+fn rows(
+    pixels: &[Vec<bool>],
+    x: usize,
+    width: usize,
+    position: &Position,
+) -> Vec<(String, Position)> {
+    pixels
+        .iter()
+        .map(|row| {
+            let row = row[x..x + width]
+                .iter()
+                .map(|&set| if set { '#' } else { '.' })
+                .collect();
+            (row, position.clone())
+        })
+        .collect()
+}
+
+/// A row of `#` and `.` as bits, the leftmost pixel highest
+fn bits(row: &str) -> u16 {
+    row.chars()
+        .fold(0, |bits, pixel| bits << 1 | (pixel == '#') as u16)
+}
@@ -1,5 +1,8 @@
-use super::exception::Position;
-use std::{fmt::Debug, rc::Rc};
+use super::{exception::Position, options::Target};
+use std::{
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
 #[derive(Clone)]
 pub struct Token {
@@ -25,9 +28,18 @@ impl Debug for Token {
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     Number(u16),
+    /// A negative number, until it is known how wide its field is
+    Negative(u16),
+    Str(String),
+    /// `1b` or `1f`, the anonymous label `1:` before or after. Where no label can go
+    /// it is the hex number it also reads as, if it is one
+    Anonymous(u16, bool, Option<u16>),
+    /// A row of a `SPRITE` block, `#` for a set pixel and `.` for a clear one
+    Pixels(String),
     Register(u8),
     Command(Command),
     Label(String),
+    Comparison(Comparison),
     MprocessorDirective(MprocessorDirective),
     Comment(String),
     Colon,
@@ -39,6 +51,7 @@ pub enum TokenType {
 #[allow(clippy::upper_case_acronyms)]
 pub enum Command {
     CHIP,
+    BYTE,
     JMPNE,
     JMPEQ,
     JMP,
@@ -70,6 +83,52 @@ pub enum Command {
     JMPEQKEY,
     JMPNEKEY,
     SYSCALL,
+    ORG,
+    ALIGN,
+    RESERVE,
+    SCROLLDOWN,
+    SCROLLRIGHT,
+    SCROLLLEFT,
+    EXIT,
+    LORES,
+    HIRES,
+    SETPTRBIGCHR,
+    SAVEFLAGS,
+    LOADFLAGS,
+    LONGPOINT,
+    DUMPRANGE,
+    LOADRANGE,
+    PLANE,
+    AUDIO,
+    PITCH,
+    JMPLT,
+    JMPGE,
+    JMPGT,
+    JMPLE,
+    TEMP,
+    INC,
+    DEC,
+    NOT,
+    NEG,
+    SKIP,
+    HALT,
+    ALIAS,
+    SPRITE,
+    IMAGE,
+    FONT,
+    TEXT,
+    TILEMAP,
+}
+
+/// The conditions `if` and `while` can test
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -77,6 +136,11 @@ pub enum Command {
 pub enum MprocessorDirective {
     M_include,
     M_error,
+    M_warning,
+    M_info,
+    M_assert,
+    M_radix,
+    M_export,
     M_define,
     M_undef,
     M_ifdef,
@@ -86,9 +150,10 @@ pub enum MprocessorDirective {
 }
 
 impl Command {
-    pub fn all() -> [Command; 32] {
+    pub fn all() -> [Command; 68] {
         [
             Command::CHIP,
+            Command::BYTE,
             Command::JMPNE,
             Command::JMPEQ,
             Command::JMP,
@@ -120,6 +185,41 @@ impl Command {
             Command::JMPEQKEY,
             Command::JMPNEKEY,
             Command::SYSCALL,
+            Command::ORG,
+            Command::ALIGN,
+            Command::RESERVE,
+            Command::SCROLLDOWN,
+            Command::SCROLLRIGHT,
+            Command::SCROLLLEFT,
+            Command::EXIT,
+            Command::LORES,
+            Command::HIRES,
+            Command::SETPTRBIGCHR,
+            Command::SAVEFLAGS,
+            Command::LOADFLAGS,
+            Command::LONGPOINT,
+            Command::DUMPRANGE,
+            Command::LOADRANGE,
+            Command::PLANE,
+            Command::AUDIO,
+            Command::PITCH,
+            Command::JMPLT,
+            Command::JMPGE,
+            Command::JMPGT,
+            Command::JMPLE,
+            Command::TEMP,
+            Command::INC,
+            Command::DEC,
+            Command::NOT,
+            Command::NEG,
+            Command::SKIP,
+            Command::HALT,
+            Command::ALIAS,
+            Command::SPRITE,
+            Command::IMAGE,
+            Command::FONT,
+            Command::TEXT,
+            Command::TILEMAP,
         ]
     }
 
@@ -131,55 +231,370 @@ impl Command {
                 [TokenType::Number(0..=0xFF), TokenType::Register(_)]
                     | [TokenType::Register(_), TokenType::Number(0..=0xFF)]
                     | [TokenType::Register(_), TokenType::Register(_)]
-            ) | (Command::RET | Command::CLR, [])
-                | (
-                    Command::CALL | Command::JMP | Command::OFFJMP,
-                    [TokenType::Label(_) | TokenType::Number(0..=0xFFF)]
-                )
+            ) | (
+                Command::RET
+                    | Command::CLR
+                    | Command::SCROLLRIGHT
+                    | Command::SCROLLLEFT
+                    | Command::EXIT
+                    | Command::LORES
+                    | Command::HIRES
+                    | Command::AUDIO
+                    | Command::SKIP
+                    | Command::HALT,
+                []
+            ) | (
+                Command::CALL | Command::JMP | Command::OFFJMP,
+                [TokenType::Label(_) | TokenType::Number(0..=0xFFF)]
+            ) | (
+                Command::SET,
+                [
+                    TokenType::Register(_),
+                    TokenType::Number(0..=0xFF) | TokenType::Register(_)
+                ]
+            ) | (
+                Command::RAND,
+                [TokenType::Register(_), TokenType::Number(0..=0xFF)]
+            ) | (
+                Command::DRAW,
+                [
+                    TokenType::Register(_),
+                    TokenType::Register(_),
+                    TokenType::Number(0..=0xF)
+                ]
+            ) | (
+                Command::OR
+                    | Command::AND
+                    | Command::XOR
+                    | Command::SUB
+                    | Command::SUBFROM
+                    | Command::DUMPRANGE
+                    | Command::LOADRANGE,
+                [TokenType::Register(_), TokenType::Register(_)]
+            ) | (
+                Command::LOAD
+                    | Command::DUMP
+                    | Command::ADDPTR
+                    | Command::SETPTRCHR
+                    | Command::SETPTRDEC
+                    | Command::GETDELAY
+                    | Command::GETKEY
+                    | Command::SETDELAY
+                    | Command::JMPEQKEY
+                    | Command::JMPNEKEY
+                    | Command::SHL
+                    | Command::SHR
+                    | Command::SETSOUND
+                    | Command::SETPTRBIGCHR
+                    | Command::SAVEFLAGS
+                    | Command::LOADFLAGS
+                    | Command::PITCH
+                    | Command::TEMP
+                    | Command::INC
+                    | Command::DEC
+                    | Command::NOT
+                    | Command::NEG,
+                [TokenType::Register(_)]
+            ) | (
+                Command::JMPLT | Command::JMPGE | Command::JMPGT | Command::JMPLE,
+                [
+                    TokenType::Register(_),
+                    TokenType::Register(_) | TokenType::Number(0..=0xFF),
+                    TokenType::Label(_) | TokenType::Number(0..=0xFFF)
+                ]
+            ) | (Command::SCROLLDOWN, [TokenType::Number(0..=0xF)])
+                | (Command::PLANE, [TokenType::Number(0..=3)])
                 | (
-                    Command::SET,
-                    [
-                        TokenType::Register(_),
-                        TokenType::Number(0..=0xFF) | TokenType::Register(_)
-                    ]
+                    Command::LONGPOINT,
+                    [TokenType::Number(_) | TokenType::Label(_)]
                 )
                 | (
-                    Command::RAND,
-                    [TokenType::Register(_), TokenType::Number(0..=0xFF)]
+                    Command::POINT,
+                    [TokenType::Number(0..=0xFFF) | TokenType::Label(_)]
                 )
                 | (
-                    Command::DRAW,
-                    [
-                        TokenType::Register(_),
-                        TokenType::Register(_),
-                        TokenType::Number(0..=0xF)
-                    ]
+                    Command::ALIAS,
+                    [TokenType::Label(_), TokenType::Register(_)]
+                        | [
+                            TokenType::Label(_),
+                            TokenType::Register(_),
+                            TokenType::Label(_)
+                        ]
                 )
+                | (Command::SPRITE | Command::TILEMAP, [TokenType::Label(_)])
                 | (
-                    Command::OR | Command::AND | Command::XOR | Command::SUB | Command::SUBFROM,
-                    [TokenType::Register(_), TokenType::Register(_)]
+                    Command::IMAGE,
+                    [TokenType::Label(_), TokenType::Str(_)]
+                        | [
+                            TokenType::Label(_),
+                            TokenType::Str(_),
+                            TokenType::Number(1..=16)
+                        ]
                 )
                 | (
-                    Command::LOAD
-                        | Command::DUMP
-                        | Command::ADDPTR
-                        | Command::SETPTRCHR
-                        | Command::SETPTRDEC
-                        | Command::GETDELAY
-                        | Command::GETKEY
-                        | Command::SETDELAY
-                        | Command::JMPEQKEY
-                        | Command::JMPNEKEY
-                        | Command::SHL
-                        | Command::SHR
-                        | Command::SETSOUND,
-                    [TokenType::Register(_)]
+                    Command::FONT,
+                    [
+                        TokenType::Label(_),
+                        TokenType::Str(_),
+                        TokenType::Number(1..=15),
+                        TokenType::Str(_)
+                    ]
                 )
                 | (
-                    Command::POINT,
-                    [TokenType::Number(0..=0xFFF) | TokenType::Label(_)]
+                    Command::TEXT,
+                    [TokenType::Label(_), TokenType::Str(_)]
+                        | [TokenType::Label(_), TokenType::Str(_), TokenType::Label(_)]
                 )
                 | (Command::SYSCALL, [TokenType::Number(0..=0xFFF)])
+                | (Command::ORG, [TokenType::Number(_)])
+                | (Command::ALIGN, [TokenType::Number(1..)])
+                | (Command::RESERVE, [TokenType::Number(_)])
         ) || (*self == Command::CHIP && args.iter().all(|tt| matches!(tt, TokenType::Number(_))))
+            || (*self == Command::BYTE
+                && args
+                    .iter()
+                    .all(|tt| matches!(tt, TokenType::Number(0..=0xFF))))
+    }
+
+    /// `JMPEQ`, `JMPNE`, `JMPEQKEY` and `JMPNEKEY` don't jump anywhere, they skip
+    /// the next two bytes when their condition holds
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Command::JMPEQ | Command::JMPNE | Command::JMPEQKEY | Command::JMPNEKEY
+        )
+    }
+
+    /// Pseudo-instructions are expanded into real instructions before layout
+    pub fn is_pseudo(&self) -> bool {
+        matches!(
+            self,
+            Command::JMPLT
+                | Command::JMPGE
+                | Command::JMPGT
+                | Command::JMPLE
+                | Command::TEMP
+                | Command::INC
+                | Command::DEC
+                | Command::NOT
+                | Command::NEG
+                | Command::SKIP
+                | Command::ALIAS
+        )
+    }
+
+    /// How many bits wide the argument at `index` is, if negative numbers can go there
+    pub fn signed_bits(&self, index: usize) -> Option<u32> {
+        match (self, index) {
+            (Command::ADD | Command::JMPEQ | Command::JMPNE | Command::BYTE, _)
+            | (
+                Command::SET
+                | Command::RAND
+                | Command::JMPLT
+                | Command::JMPGE
+                | Command::JMPGT
+                | Command::JMPLE,
+                1,
+            ) => Some(8),
+            (Command::CHIP, _) => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Whether the argument at `index` can be a label
+    pub fn takes_label(&self, index: usize) -> bool {
+        matches!(
+            (self, index),
+            (
+                Command::JMP
+                    | Command::CALL
+                    | Command::OFFJMP
+                    | Command::POINT
+                    | Command::LONGPOINT,
+                0
+            ) | (
+                Command::JMPLT | Command::JMPGE | Command::JMPGT | Command::JMPLE,
+                2
+            )
+        )
+    }
+
+    /// Bytes the instruction takes up once assembled
+    pub fn size(&self, args: &[TokenType]) -> usize {
+        match self {
+            Command::CHIP => 2 * args.len(),
+            Command::BYTE => args.len(),
+            Command::LONGPOINT => 4,
+            _ => 2,
+        }
+    }
+
+    /// The skip with the opposite condition
+    pub fn inverse_skip(&self) -> Command {
+        match self {
+            Command::JMPEQ => Command::JMPNE,
+            Command::JMPNE => Command::JMPEQ,
+            Command::JMPEQKEY => Command::JMPNEKEY,
+            Command::JMPNEKEY => Command::JMPEQKEY,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The first instruction set that has this instruction
+    pub fn target(&self, args: &[TokenType]) -> Target {
+        match (self, args) {
+            (
+                Command::SCROLLDOWN
+                | Command::SCROLLRIGHT
+                | Command::SCROLLLEFT
+                | Command::EXIT
+                | Command::LORES
+                | Command::HIRES
+                | Command::SETPTRBIGCHR,
+                _,
+            )
+            | (Command::SAVEFLAGS | Command::LOADFLAGS, [TokenType::Register(0..=7)])
+            | (Command::DRAW, [_, _, TokenType::Number(0)]) => Target::SChip,
+            (
+                Command::SAVEFLAGS
+                | Command::LOADFLAGS
+                | Command::LONGPOINT
+                | Command::DUMPRANGE
+                | Command::LOADRANGE
+                | Command::PLANE
+                | Command::AUDIO
+                | Command::PITCH,
+                _,
+            )
+            | (Command::ORG, [TokenType::Number(0x1000..)]) => Target::XOChip,
+            _ => Target::Chip8,
+        }
+    }
+}
+
+impl Display for TokenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenType::Number(n) => write!(f, "0x{:X}", n),
+            TokenType::Negative(n) => write!(f, "-0x{:X}", n),
+            TokenType::Str(s) => write!(f, "{}", string(s)),
+            TokenType::Anonymous(n, forward, _) => {
+                write!(f, "{:X}{}", n, if *forward { 'f' } else { 'b' })
+            }
+            TokenType::Pixels(p) => write!(f, "{}", p),
+            TokenType::Register(r) => write!(f, "v{:X}", r),
+            TokenType::Command(c) => write!(f, "{:?}", c),
+            TokenType::Label(l) => write!(f, "{}", l),
+            TokenType::Comparison(c) => write!(f, "{}", c.symbol()),
+            TokenType::MprocessorDirective(d) => write!(f, "{}", d.symbol()),
+            TokenType::Comment(c) => write!(f, ";{}", c),
+            TokenType::Colon => write!(f, ":"),
+            TokenType::Eof | TokenType::Eol => Ok(()),
+        }
+    }
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Ge => ">=",
+            Comparison::Gt => ">",
+            Comparison::Le => "<=",
+        }
+    }
+}
+
+impl MprocessorDirective {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            MprocessorDirective::M_include => "%+",
+            MprocessorDirective::M_error => "%!",
+            MprocessorDirective::M_warning => "%warning",
+            MprocessorDirective::M_info => "%info",
+            MprocessorDirective::M_assert => "%assert",
+            MprocessorDirective::M_radix => "%radix",
+            MprocessorDirective::M_export => "%export",
+            MprocessorDirective::M_define => "%#+",
+            MprocessorDirective::M_undef => "%#-",
+            MprocessorDirective::M_ifdef => "%?#",
+            MprocessorDirective::M_ifndef => "%?!",
+            MprocessorDirective::M_else => "%?|",
+            MprocessorDirective::M_endif => "%?-",
+        }
+    }
+}
+
+/// Turns tokens back into source, one statement per line. Whenever the tokens jump
+/// to another file or line a `;#line LINE "FILE"` marker records where they came from.
+/// A string as the lexer reads it back. Control characters it has no escape for
+/// are written as numbers between the quoted parts, which `BYTE` takes the same way.
+fn string(s: &str) -> String {
+    let mut parts = Vec::new();
+    let mut quoted = String::new();
+    for c in s.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => {
+                if !quoted.is_empty() {
+                    parts.push(format!("\"{}\"", quoted));
+                    quoted.clear();
+                }
+                let mut bytes = [0; 4];
+                for b in c.encode_utf8(&mut bytes).bytes() {
+                    parts.push(format!("0x{:X}", b));
+                }
+            }
+            c => quoted.push(c),
+        }
+    }
+    if !quoted.is_empty() || parts.is_empty() {
+        parts.push(format!("\"{}\"", quoted));
+    }
+    parts.join(" ")
+}
+
+pub fn to_source(tokens: &[Token]) -> String {
+    let mut source = String::new();
+    let mut last: Option<&Position> = None;
+    for token in tokens {
+        if matches!(
+            token.token,
+            TokenType::Comment(_) | TokenType::Eol | TokenType::Eof
+        ) {
+            continue;
+        }
+        let position = &token.position;
+        match last {
+            Some(last) if last.file == position.file && last.line == position.line => {
+                if token.token != TokenType::Colon {
+                    source.push(' ');
+                }
+            }
+            _ => {
+                if last.is_some() {
+                    source.push('\n');
+                }
+                if !matches!(last, Some(last) if last.file == position.file && last.line + 1 == position.line)
+                {
+                    source.push_str(&format!(";#line {} \"{}\"\n", position.line, position.file));
+                }
+                if !matches!(token.token, TokenType::Label(_)) {
+                    source.push_str("    ");
+                }
+            }
+        }
+        source.push_str(&token.token.to_string());
+        last = Some(position);
     }
+    source.push('\n');
+    source
 }